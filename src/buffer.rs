@@ -1,31 +1,405 @@
-use ropey::Rope;
+use crate::encoding::{Encoding, FileFormat};
+use crate::fileio::{self, DiskState, Hasher, OpenError};
+use crate::follow::Follow;
+use crate::hex::HexView;
+use crate::history::{Edit, History, Transaction};
+use ropey::{Rope, RopeBuilder};
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::SystemTime;
 
 pub struct Buffer {
+    /// Buffer number shown by `:ls` and used by `:b N`.
+    pub id: usize,
     pub content: Rope,
+    pub path: Option<String>,
+    /// Line endings and encoding the file is written with.
+    pub fileformat: FileFormat,
+    pub encoding: Encoding,
+    /// Undo state and file format the file on disk matches.
+    saved_state: usize,
+    saved_format: (FileFormat, Encoding),
+    /// `changes` when the file was last read or written, which tells whether
+    /// a large file is modified as it keeps no undo history.
+    saved_changes: u64,
+    /// Loaded in large-file mode: no undo history, syntax colouring or
+    /// undo and swap file contents.
+    pub large: bool,
+    /// The file as last read or written, to notice other programs changing it.
+    pub disk: Option<DiskState>,
+    /// Refuses changes, and `:w` without `!`.
+    pub readonly: bool,
+    /// Off for buffers that must not be changed at all, even with `!`.
+    pub modifiable: bool,
+    /// The raw bytes being edited while in hex mode, which are written
+    /// instead of the text.
+    pub hex: Option<HexView>,
+    /// Set while the file is followed and new bytes are appended as they arrive.
+    pub follow: Option<Follow>,
+    /// Swap file this buffer keeps its unsaved changes in.
+    pub swap_path: Option<PathBuf>,
+    /// `changes()` when the swap file was last written.
+    pub swap_changes: Option<u64>,
+    /// Counts every change to the text, including undo and redo.
+    changes: u64,
+    /// Cursor and scroll offset to restore when the buffer is shown again.
+    pub cursor: (usize, usize),
+    pub scroll_offset: (usize, usize),
+    /// Marks set with `m`, plus `'<` / `'>` for the last Visual selection.
+    pub marks: HashMap<char, (usize, usize)>,
+    pub history: History,
+    /// Edits made since the last committed transaction.
+    pending: Vec<Edit>,
 }
 
 impl Buffer {
     pub fn new() -> Self {
-        Self {
-            content: Rope::new(),
-        }
+        Self::from_str("")
     }
 
     pub fn from_str(text: &str) -> Self {
         Self {
+            id: 1,
             content: Rope::from_str(text),
+            path: None,
+            fileformat: FileFormat::Unix,
+            encoding: Encoding::Utf8,
+            saved_state: 0,
+            saved_format: (FileFormat::Unix, Encoding::Utf8),
+            saved_changes: 0,
+            large: false,
+            disk: None,
+            readonly: false,
+            modifiable: true,
+            hex: None,
+            follow: None,
+            swap_path: None,
+            swap_changes: None,
+            changes: 0,
+            cursor: (0, 0),
+            scroll_offset: (0, 0),
+            marks: HashMap::new(),
+            history: History::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Reads `path`, detecting its encoding and line endings. Files of
+    /// `large_threshold` bytes or more are streamed in and opened in
+    /// large-file mode; `progress` is told the bytes read so far and in all.
+    pub fn load(
+        path: &str,
+        large_threshold: u64,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<Self, OpenError> {
+        let meta = std::fs::metadata(path).map_err(|e| OpenError::new(path, e))?;
+        if meta.is_dir() {
+            return Err(OpenError::IsDirectory(path.to_string()));
+        }
+        if meta.len() >= large_threshold {
+            return Self::load_large(path, &meta, progress);
+        }
+        let data = std::fs::read(path).map_err(|e| OpenError::new(path, e))?;
+        let (text, encoding) = Encoding::decode(&data);
+        let fileformat = FileFormat::detect(&text);
+        let mut buffer = Self::from_str(&fileformat.to_buffer(text));
+        buffer.path = Some(path.to_string());
+        buffer.fileformat = fileformat;
+        buffer.encoding = encoding;
+        let mut hasher = Hasher::new();
+        hasher.update(&data);
+        buffer.disk = Some(DiskState::new(&meta, hasher.finish()));
+        buffer.mark_saved();
+        Ok(buffer)
+    }
+
+    /// Reads a large file chunk by chunk straight into the rope. Line endings
+    /// are kept as they are, so the file is written back unchanged.
+    fn load_large(
+        path: &str,
+        meta: &Metadata,
+        progress: &mut dyn FnMut(u64, u64),
+    ) -> Result<Self, OpenError> {
+        let error = |e| OpenError::new(path, e);
+        let mut file = File::open(path).map_err(error)?;
+        let mut start = [0; 2];
+        let peeked = file.read(&mut start).map_err(error)?;
+        let (content, encoding, hash) = if matches!(&start[..peeked], b"\xFF\xFE" | b"\xFE\xFF") {
+            // UTF-16 is rare enough in logs to be decoded in one go.
+            let data = std::fs::read(path).map_err(error)?;
+            let (text, encoding) = Encoding::decode(&data);
+            let mut hasher = Hasher::new();
+            hasher.update(&data);
+            (Rope::from_str(&text), encoding, hasher.finish())
+        } else {
+            match stream_text(path, meta.len(), false, progress).map_err(error)? {
+                Some(loaded) => loaded,
+                None => stream_text(path, meta.len(), true, progress)
+                    .map_err(error)?
+                    .expect("Latin-1 decodes any bytes"),
+            }
+        };
+        let mut buffer = Self::new();
+        buffer.content = content;
+        buffer.path = Some(path.to_string());
+        buffer.encoding = encoding;
+        buffer.large = true;
+        buffer.disk = Some(DiskState::new(meta, hash));
+        buffer.mark_saved();
+        Ok(buffer)
+    }
+
+    /// Writes the text as it goes to disk, a rope chunk at a time.
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        if let Some(hex) = &self.hex {
+            return out.write_all(&hex.bytes);
+        }
+        out.write_all(self.encoding.bom())?;
+        for chunk in self.content.chunks() {
+            out.write_all(&self.encoding.encode(&self.fileformat.to_file(chunk)))?;
         }
+        Ok(())
+    }
+
+    /// The first char the encoding cannot represent.
+    pub fn unencodable(&self) -> Option<char> {
+        if self.hex.is_some() {
+            return None;
+        }
+        self.encoding.unencodable(self.content.chars())
+    }
+
+    /// Has NUL chars near the start, which text files don't.
+    pub fn is_binary(&self) -> bool {
+        self.content.chars().take(8192).any(|c| c == '\0')
+    }
+
+    /// File name for display, `[No Name]` for a buffer without a file.
+    pub fn name(&self) -> &str {
+        self.path.as_deref().unwrap_or("[No Name]")
+    }
+
+    /// Line `row` without its line break; empty past the last line.
+    pub fn line_text(&self, row: usize) -> String {
+        if row >= self.content.len_lines() {
+            return String::new();
+        }
+        let mut line = self.content.line(row).to_string();
+        line.truncate(line.trim_end_matches(['\n', '\r']).len());
+        line
     }
 
     pub fn insert_char(&mut self, char_idx: usize, c: char) {
         if char_idx <= self.content.len_chars() {
-            self.content.insert_char(char_idx, c);
+            self.insert(char_idx, c.encode_utf8(&mut [0; 4]));
         }
     }
 
-    pub fn delete_char(&mut self, char_idx: usize) {
-        if char_idx < self.content.len_chars() {
-            self.content.remove(char_idx..char_idx + 1);
+    pub fn insert(&mut self, char_idx: usize, text: &str) {
+        if text.is_empty() {
+            return;
         }
+        self.content.insert(char_idx, text);
+        self.changes += 1;
+        // Typing extends the previous insert instead of logging every char.
+        if let Some(last) = self.pending.last_mut()
+            && last.removed.is_empty()
+            && last.pos + last.inserted.chars().count() == char_idx
+        {
+            last.inserted.push_str(text);
+            return;
+        }
+        self.pending.push(Edit {
+            pos: char_idx,
+            removed: String::new(),
+            inserted: text.to_string(),
+        });
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let removed = self.content.slice(range.clone()).to_string();
+        self.content.remove(range.clone());
+        self.changes += 1;
+        self.pending.push(Edit {
+            pos: range.start,
+            removed,
+            inserted: String::new(),
+        });
+    }
+
+    /// Groups the pending edits into one undoable transaction. Returns false
+    /// when nothing changed.
+    pub fn commit(&mut self, cursor_before: (usize, usize), cursor_after: (usize, usize)) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+        if self.large {
+            self.pending.clear();
+            return true;
+        }
+        self.history.push(Transaction {
+            edits: std::mem::take(&mut self.pending),
+            cursor_before,
+            cursor_after,
+            time: SystemTime::now(),
+        });
+        true
+    }
+
+    /// Position in the pending edit log, to pass to `edits_since` later.
+    pub fn edit_mark(&self) -> (usize, usize) {
+        let inserted = self
+            .pending
+            .last()
+            .map_or(0, |e| e.inserted.chars().count());
+        (self.pending.len(), inserted)
+    }
+
+    /// Pending edits made since `mark` as `(pos, removed, inserted)` char
+    /// counts. Text merged into the edit at the mark is reported on its own.
+    pub fn edits_since(&self, (len, inserted): (usize, usize)) -> Vec<(usize, usize, usize)> {
+        let mut edits = Vec::new();
+        if let Some(last) = len.checked_sub(1).and_then(|i| self.pending.get(i)) {
+            let grown = last.inserted.chars().count().saturating_sub(inserted);
+            if grown > 0 {
+                edits.push((last.pos + inserted, 0, grown));
+            }
+        }
+        edits.extend(
+            self.pending[len.min(self.pending.len())..]
+                .iter()
+                .map(|e| (e.pos, e.removed.chars().count(), e.inserted.chars().count())),
+        );
+        edits
+    }
+
+    pub fn has_pending_edits(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Changed since it was last read or written: there are uncommitted
+    /// edits, undo/redo has left the state the file was saved in, or it is
+    /// to be written with other line endings or encoding. Large files have
+    /// no undo states, so any change since then counts.
+    pub fn modified(&self) -> bool {
+        let changed = if self.large {
+            self.changes != self.saved_changes
+        } else {
+            !self.pending.is_empty() || self.history.current() != self.saved_state
+        };
+        changed
+            || (self.fileformat, self.encoding) != self.saved_format
+            || self.hex.as_ref().is_some_and(|hex| hex.dirty)
+    }
+
+    /// Records that the file on disk now matches the current undo state.
+    pub fn mark_saved(&mut self) {
+        self.saved_state = self.history.current();
+        self.saved_format = (self.fileformat, self.encoding);
+        self.saved_changes = self.changes;
+        if let Some(hex) = &mut self.hex {
+            hex.dirty = false;
+        }
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes
+    }
+
+    /// Adds text read from the followed file. It is not an edit: it cannot
+    /// be undone and leaves the buffer unmodified.
+    pub fn append_followed(&mut self, text: &str) {
+        self.content.insert(self.content.len_chars(), text);
+    }
+
+    /// Replaces the text with the followed file's new contents, dropping the
+    /// undo history, which no longer applies.
+    pub fn reset_followed(&mut self, text: &str) {
+        self.content = Rope::from_str(text);
+        self.history = History::new();
+        self.pending.clear();
+        self.changes += 1;
+        self.mark_saved();
+    }
+
+    pub fn undo(&mut self) -> Option<(usize, usize)> {
+        let cursor = self.history.undo(&mut self.content);
+        self.changes += cursor.is_some() as u64;
+        cursor
+    }
+
+    pub fn redo(&mut self) -> Option<(usize, usize)> {
+        let cursor = self.history.redo(&mut self.content);
+        self.changes += cursor.is_some() as u64;
+        cursor
+    }
+
+    /// Jumps to undo state `target`, possibly on another branch.
+    pub fn goto_state(&mut self, target: usize) -> Option<(usize, usize)> {
+        let cursor = self.history.goto(target, &mut self.content);
+        self.changes += cursor.is_some() as u64;
+        cursor
+    }
+}
+
+/// Streams the file at `path` into a rope, as Latin-1 or as UTF-8 with an
+/// optional byte order mark. Returns the text, its encoding and the hash of
+/// the file, or `None` when it is not valid UTF-8.
+fn stream_text(
+    path: &str,
+    len: u64,
+    latin1: bool,
+    progress: &mut dyn FnMut(u64, u64),
+) -> io::Result<Option<(Rope, Encoding, u64)>> {
+    let mut file = File::open(path)?;
+    let mut builder = RopeBuilder::new();
+    let mut hasher = Hasher::new();
+    let mut encoding = if latin1 {
+        Encoding::Latin1
+    } else {
+        Encoding::Utf8
+    };
+    let mut chunk = vec![0; fileio::CHUNK_SIZE];
+    // Bytes of a UTF-8 sequence split across two chunks.
+    let mut carry: Vec<u8> = Vec::new();
+    let mut read = 0;
+    loop {
+        let n = file.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        let mut bytes = &chunk[..n];
+        hasher.update(bytes);
+        if read == 0
+            && !latin1
+            && let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF")
+        {
+            encoding = Encoding::Utf8Bom;
+            bytes = rest;
+        }
+        read += n as u64;
+        progress(read, len);
+        if latin1 {
+            builder.append(&bytes.iter().map(|&b| b as char).collect::<String>());
+            continue;
+        }
+        carry.extend_from_slice(bytes);
+        let valid = match std::str::from_utf8(&carry) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Ok(None),
+        };
+        builder.append(std::str::from_utf8(&carry[..valid]).expect("checked above"));
+        carry.drain(..valid);
+    }
+    if !carry.is_empty() {
+        return Ok(None);
     }
+    Ok(Some((builder.finish(), encoding, hasher.finish())))
 }
@@ -0,0 +1,53 @@
+use crate::mode::Mode;
+use crate::motion::Motion;
+use crate::textobject::TextObject;
+
+/// What a repeatable change acted on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeTarget {
+    Motion(Motion),
+    TextObject(TextObject),
+    /// Whole lines, as with `dd` or `cc`.
+    Lines,
+    /// A Visual selection, replayed as the same extent from the cursor: `rows`
+    /// extra lines and `cols` columns (for charwise selections spanning lines,
+    /// the end column).
+    Visual {
+        mode: Mode,
+        rows: usize,
+        cols: usize,
+    },
+    /// A plain `i` insert session.
+    Insert,
+    Paste {
+        before: bool,
+    },
+}
+
+/// A replayable unit for `.`: operator, target, count, register and the text
+/// typed during the Insert session the change started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub op: char,
+    pub target: ChangeTarget,
+    pub count: Option<usize>,
+    pub register: Option<char>,
+    pub inserted: String,
+}
+
+impl Change {
+    pub fn new(
+        op: char,
+        target: ChangeTarget,
+        count: Option<usize>,
+        register: Option<char>,
+    ) -> Self {
+        Self {
+            op,
+            target,
+            count,
+            register,
+            inserted: String::new(),
+        }
+    }
+}
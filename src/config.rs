@@ -1,12 +1,45 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Config {
     pub theme: String,
     pub show_line_numbers: bool,
     pub tab_size: usize,
+    /// Keep undo history across sessions.
+    pub undo_file: bool,
+    /// Where undo files are written; defaults to `$XDG_DATA_HOME/rune/undo`.
+    pub undo_dir: Option<String>,
+    /// Match case-insensitively in searches.
+    pub ignorecase: bool,
+    /// With `ignorecase`, a pattern containing uppercase matches case.
+    pub smartcase: bool,
+    /// Highlight all matches of the last search.
+    pub hlsearch: bool,
+    /// Jump to and highlight matches while a search is typed.
+    pub incsearch: bool,
+    /// Save by writing a temporary file and renaming it over the original,
+    /// so a failed write never leaves a truncated file behind.
+    pub atomic_save: bool,
+    /// Keep a copy of the previous version of a file when saving over it.
+    pub backup: bool,
+    /// Where backups are written; defaults to `{file}.bak` beside the file.
+    pub backup_dir: Option<String>,
+    /// Flush saved files to disk before reporting them written.
+    pub fsync: bool,
+    /// Keep unsaved changes in a swap file to recover them after a crash.
+    pub swap_file: bool,
+    /// Where swap files are written; defaults to `$XDG_DATA_HOME/rune/swap`.
+    pub swap_dir: Option<String>,
+    /// Milliseconds between swap file updates.
+    pub update_time: u64,
+    /// Reload buffers without unsaved changes when their file changes on disk.
+    pub autoread: bool,
+    /// Files of this many bytes or more open in large-file mode: streamed
+    /// in, without undo history or syntax colouring.
+    pub large_file_threshold: u64,
 }
 
 impl Default for Config {
@@ -15,6 +48,21 @@ impl Default for Config {
             theme: String::from("dark"),
             show_line_numbers: true,
             tab_size: 4,
+            undo_file: true,
+            undo_dir: None,
+            ignorecase: false,
+            smartcase: false,
+            hlsearch: true,
+            incsearch: true,
+            atomic_save: true,
+            backup: false,
+            backup_dir: None,
+            fsync: true,
+            swap_file: true,
+            swap_dir: None,
+            update_time: 4000,
+            autoread: false,
+            large_file_threshold: 64 * 1024 * 1024,
         }
     }
 }
@@ -22,13 +70,35 @@ impl Default for Config {
 impl Config {
     pub fn load() -> Self {
         let config_path = "rune.toml";
-        if Path::new(config_path).exists() {
-            if let Ok(content) = fs::read_to_string(config_path) {
-                if let Ok(config) = toml::from_str(&content) {
-                    return config;
-                }
-            }
+        if Path::new(config_path).exists()
+            && let Ok(content) = fs::read_to_string(config_path)
+            && let Ok(config) = toml::from_str(&content)
+        {
+            return config;
         }
         Self::default()
     }
+
+    pub fn undo_dir(&self) -> PathBuf {
+        if let Some(dir) = &self.undo_dir {
+            return PathBuf::from(dir);
+        }
+        data_dir().join("undo")
+    }
+
+    pub fn swap_dir(&self) -> PathBuf {
+        if let Some(dir) = &self.swap_dir {
+            return PathBuf::from(dir);
+        }
+        data_dir().join("swap")
+    }
+}
+
+/// RUNE's per-user data directory, following the XDG base directory spec.
+fn data_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join("rune");
+    }
+    let home = std::env::var_os("HOME").unwrap_or_else(|| ".".into());
+    PathBuf::from(home).join(".local/share/rune")
 }
@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A grapheme cluster of a line and where it shows on screen.
+pub struct Grapheme<'a> {
+    pub text: &'a str,
+    /// Char column it starts at.
+    pub col: usize,
+    /// Screen column it starts at.
+    pub vcol: usize,
+    /// Screen columns it takes.
+    pub width: usize,
+}
+
+impl Grapheme<'_> {
+    /// What is drawn for it: tabs as spaces, control chars in caret notation
+    /// (`^M`), and a cluster of no width of its own after a space.
+    pub fn shown(&self) -> Cow<'_, str> {
+        match self.text.chars().next() {
+            Some('\t') => Cow::Owned(" ".repeat(self.width)),
+            Some(c) if c.is_control() => Cow::Owned(self.text.chars().map(caret).collect()),
+            _ if self.text.width() == 0 => Cow::Owned(format!(" {}", self.text)),
+            _ => Cow::Borrowed(self.text),
+        }
+    }
+}
+
+/// The grapheme clusters of `line` (without its line break), with tab stops
+/// every `tab_size` columns.
+pub fn graphemes(line: &str, tab_size: usize) -> impl Iterator<Item = Grapheme<'_>> {
+    let (mut col, mut vcol) = (0, 0);
+    line.graphemes(true).map(move |text| {
+        let width = match text.chars().next() {
+            Some('\t') => tab_size.max(1) - vcol % tab_size.max(1),
+            Some(c) if c.is_control() => text.chars().map(|c| caret(c).len()).sum(),
+            _ => text.width().max(1),
+        };
+        let grapheme = Grapheme {
+            text,
+            col,
+            vcol,
+            width,
+        };
+        col += text.chars().count();
+        vcol += width;
+        grapheme
+    })
+}
+
+/// `^@`..`^_` and `^?`; other control chars show as `?`.
+fn caret(c: char) -> String {
+    match c {
+        '\0'..='\u{1f}' => format!("^{}", (c as u8 + b'@') as char),
+        '\u{7f}' => String::from("^?"),
+        _ => String::from("?"),
+    }
+}
+
+/// Char column `n` grapheme clusters after (or, for negative `n`, before) the
+/// cluster containing `col`; `0` gives that cluster's start. Columns past the
+/// end of the line count one each.
+pub fn step(line: &str, col: usize, n: isize) -> usize {
+    let mut stops: Vec<usize> = graphemes(line, 1).map(|g| g.col).collect();
+    let end = line.chars().count();
+    stops.push(end);
+    let i = stops.partition_point(|&stop| stop <= col) - 1;
+    let past_end = if i == stops.len() - 1 { col - end } else { 0 };
+    let target = (i + past_end).saturating_add_signed(n);
+    match stops.get(target) {
+        Some(&stop) => stop,
+        None => end + target - (stops.len() - 1),
+    }
+}
+
+/// Screen column where char column `col` of `line` shows; columns past its end
+/// count one each.
+pub fn vcol(line: &str, col: usize, tab_size: usize) -> usize {
+    let (mut end_col, mut end_vcol) = (0, 0);
+    for g in graphemes(line, tab_size) {
+        end_col = g.col + g.text.chars().count();
+        if col < end_col {
+            return g.vcol;
+        }
+        end_vcol = g.vcol + g.width;
+    }
+    end_vcol + (col - end_col)
+}
+
+/// Char column of the grapheme cluster shown at screen column `vcol`, or the
+/// end of the line when `vcol` is past it.
+pub fn col_at(line: &str, vcol: usize, tab_size: usize) -> usize {
+    let mut end = 0;
+    for g in graphemes(line, tab_size) {
+        if vcol < g.vcol + g.width {
+            return g.col;
+        }
+        end = g.col + g.text.chars().count();
+    }
+    end
+}
+
+/// The part of `line` shown from screen column `first` on, at most `width`
+/// columns of it, with the char column each char drawn comes from. Clusters
+/// cut by either edge show as spaces.
+pub fn visible(line: &str, first: usize, width: usize, tab_size: usize) -> (String, Vec<usize>) {
+    let last = first + width;
+    let (mut text, mut cols) = (String::new(), Vec::new());
+    for g in graphemes(line, tab_size) {
+        if g.vcol >= last {
+            break;
+        }
+        if g.vcol + g.width <= first {
+            continue;
+        }
+        let shown = if g.vcol < first || g.vcol + g.width > last {
+            let cells = (g.vcol + g.width).min(last) - g.vcol.max(first);
+            Cow::Owned(" ".repeat(cells))
+        } else {
+            g.shown()
+        };
+        for c in shown.chars() {
+            text.push(c);
+            cols.push(g.col);
+        }
+    }
+    (text, cols)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `e` with a combining acute accent: one cluster of two chars.
+    const E_ACUTE: &str = "e\u{301}";
+
+    #[test]
+    fn steps_move_by_clusters() {
+        let line = format!("a{}漢b", E_ACUTE);
+        assert_eq!(step(&line, 0, 1), 1);
+        assert_eq!(step(&line, 1, 1), 3);
+        // From inside a cluster, `0` gives its start.
+        assert_eq!(step(&line, 2, 0), 1);
+        assert_eq!(step(&line, 3, -1), 1);
+        assert_eq!(step(&line, 0, -5), 0);
+        assert_eq!(step(&line, 4, 1), 5);
+        // Past the end of the line every column counts.
+        assert_eq!(step(&line, 5, 2), 7);
+        assert_eq!(step(&line, 7, -3), 4);
+        assert_eq!(step("", 0, 1), 1);
+    }
+
+    #[test]
+    fn screen_columns() {
+        let line = format!("\t漢{}x", E_ACUTE);
+        assert_eq!(vcol(&line, 0, 4), 0);
+        assert_eq!(vcol(&line, 1, 4), 4);
+        assert_eq!(vcol(&line, 2, 4), 6);
+        assert_eq!(vcol(&line, 3, 4), 6);
+        assert_eq!(vcol(&line, 4, 4), 7);
+        assert_eq!(vcol(&line, 6, 4), 9);
+        assert_eq!(col_at(&line, 3, 4), 0);
+        assert_eq!(col_at(&line, 5, 4), 1);
+        assert_eq!(col_at(&line, 6, 4), 2);
+        assert_eq!(col_at(&line, 40, 4), 5);
+        // Tabs stop at the next multiple of the tab size.
+        assert_eq!(vcol("ab\tc", 3, 4), 4);
+        assert_eq!(vcol("\u{1}x", 1, 4), 2);
+    }
+
+    #[test]
+    fn visible_parts() {
+        let (text, cols) = visible("a\tb", 0, 10, 4);
+        assert_eq!(text, "a   b");
+        assert_eq!(cols, vec![0, 1, 1, 1, 2]);
+        // A wide char cut by either edge shows as blanks.
+        assert_eq!(visible("漢字x", 1, 3, 4).0, " 字");
+        assert_eq!(visible("漢字x", 0, 3, 4).0, "漢 ");
+        assert_eq!(visible("a\u{7f}\rb", 0, 10, 4).0, "a^?^Mb");
+        assert_eq!(visible(E_ACUTE, 0, 1, 4).0, E_ACUTE);
+        assert_eq!(visible("\u{301}", 0, 1, 4).0, " \u{301}");
+    }
+}
@@ -1,332 +1,3548 @@
 use crate::buffer::Buffer;
+use crate::change::{Change, ChangeTarget};
+use crate::config::Config;
+use crate::display;
+use crate::encoding::{Encoding, FileFormat};
+use crate::ex::{self, Address, ExRange, LastSubstitute, LineAnchor, LineSpec, Substitution};
+use crate::fileio::{self, DiskChange, DiskState, OpenError};
+use crate::follow::{Follow, FollowUpdate};
+use crate::hex::{self, HexView, ROW_BYTES};
+use crate::keys;
 use crate::mode::Mode;
-use crossterm::event::KeyCode;
+use crate::motion::Motion;
+use crate::register::{Register, RegisterKind, Registers};
+use crate::search::{self, SearchOffset};
+use crate::swapfile::{self, SwapFile};
+use crate::textobject::TextObject;
+use crate::undofile;
+use crate::watcher::{self, FileWatcher};
+use crate::window::{Layout, Rect, SplitDir, TabPage, Window};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use regex::Regex;
+use ropey::Rope;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const NOT_SAVED: &str = "E37: No write since last change (add ! to override)";
+const SWAP_CHOICES: &str =
+    "[r]ecover, [o]pen read-only, [e]dit anyway, [d]elete swap file, [q]uit:";
+const CHANGED_ON_DISK: &str =
+    "WARNING: The file has been changed since reading it (add ! to override)";
+const RELOAD_CHOICES: &str = "[o]k, (l)oad file:";
+const READONLY: &str = "E45: 'readonly' option is set (add ! to override)";
+const UNDO_OFF: &str = "Undo is off for large files";
+const NOT_MODIFIABLE: &str = "E21: Cannot make changes, 'modifiable' is off";
+const READONLY_CHANGE: &str = "Cannot change a read-only buffer (:set noro to allow changes)";
+const HEX_ONLY: &str = "Not available in hex mode (:hex to leave it)";
+const TOO_LONG: &str = "E1240: Resulting text too long";
+/// Most text a count may repeat a paste or insert into, so a mistyped count
+/// cannot exhaust memory.
+const MAX_REPEAT_BYTES: usize = 64 << 20;
+
+/// Reports progress of a long-running operation, such as loading a large file.
+pub type Progress = dyn Fn(&str);
+
+/// `pending_prefix` while waiting for the key after `Ctrl-w`.
+const WINDOW_PREFIX: char = '\u{17}';
+
+/// Pending replication of text typed after a blockwise `c`.
+pub struct BlockInsert {
+    pub row: usize,
+    pub col: usize,
+    pub rows: Vec<usize>,
+}
 
 pub struct Editor {
+    pub config: Config,
+    /// The buffer being edited; the other listed buffers live in `buffers`.
     pub buffer: Buffer,
+    pub buffers: Vec<Buffer>,
+    pub next_buffer_id: usize,
+    pub windows: Vec<Window>,
+    pub layout: Layout,
+    /// The window showing `buffer`; its cursor and scroll offset are
+    /// `cursor` and `scroll_offset`.
+    pub current_window: usize,
+    pub next_window_id: usize,
+    /// Every tab page. The current one lives in `windows`, `layout` and
+    /// `current_window`; its entry here is only updated when leaving it.
+    pub tabs: Vec<TabPage>,
+    pub current_tab: usize,
     pub cursor: (usize, usize),        // (row, col)
-    pub scroll_offset: (usize, usize), // (row, col)
+    pub scroll_offset: (usize, usize), // (row, screen column)
+    /// Screen column `j` and `k` keep to (`usize::MAX` after `$`), with the
+    /// cursor it applies at; moving the cursor any other way drops it.
+    pub curswant: Option<(usize, (usize, usize))>,
     pub terminal_size: (u16, u16),
     pub mode: Mode,
-    pub clipboard: String,
+    pub registers: Registers,
+    pub pending_register: Option<char>,
+    pub insert_text: String,
+    pub last_change: Option<Change>,
+    pub pending_change: Option<Change>,
+    pub recording: Option<char>,
+    pub recorded_keys: Vec<KeyEvent>,
+    pub last_macro: Option<char>,
+    pub macro_depth: usize,
     pub pending_operator: Option<char>,
+    pub pending_prefix: Option<char>,
+    pub count: Option<usize>,
+    pub operator_count: Option<usize>,
+    pub command_count: Option<usize>,
+    pub visual_anchor: (usize, usize), // (row, col)
+    pub block_insert: Option<BlockInsert>,
     pub command_buffer: String,
     pub search_query: String,
     pub last_search_dir: bool, // true for forward (/), false for backward (?)
-    pub undo_stack: Vec<ropey::Rope>,
-    pub redo_stack: Vec<ropey::Rope>,
-    pub file_path: Option<String>,
+    pub search_offset: SearchOffset,
+    pub search_regex: Option<Regex>,
+    /// Whether matches of the last search are highlighted; cleared by `:noh`.
+    pub search_highlight: bool,
+    /// Pattern typed so far while incsearch is previewing it.
+    pub incsearch_regex: Option<Regex>,
+    pub search_origin: (usize, usize), // cursor when `/` or `?` was pressed
+    /// Cursor index and match start of the last search jump, so a repeat with
+    /// an offset continues from the match rather than the offset position.
+    pub last_match: Option<(usize, usize)>,
+    pub undo_cursor: (usize, usize), // cursor before the change in progress
+    pub last_substitute: Option<LastSubstitute>,
+    /// A `:s///c` waiting for the user to answer at its current match.
+    pub substitution: Option<Substitution>,
+    /// Swap file found for the file just opened, waiting for the user to
+    /// choose what to do with it.
+    pub swap_prompt: Option<(PathBuf, SwapFile)>,
+    last_swap_write: Instant,
+    /// Buffer whose file changed on disk, waiting for the user to choose
+    /// between keeping the buffer and loading the file.
+    pub reload_prompt: Option<usize>,
+    /// Reports files changed by other programs; set up by `main`, which owns
+    /// the runtime it runs on.
+    pub watcher: Option<FileWatcher>,
+    /// Shows progress while a large file loads; set up by `main`, which draws
+    /// it straight to the terminal.
+    pub progress: Option<Box<Progress>>,
+    /// Substitutions and lines changed by the running `:g`; `Some` while it runs.
+    pub global_substitutions: Option<(usize, usize)>,
     pub status_message: String,
     pub should_quit: bool,
 }
 
 impl Editor {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
         Self {
+            config,
             buffer: Buffer::new(),
+            buffers: Vec::new(),
+            next_buffer_id: 2,
+            windows: vec![Window {
+                id: 1,
+                buffer_id: 1,
+                cursor: (0, 0),
+                scroll_offset: (0, 0),
+            }],
+            layout: Layout::Window(1),
+            current_window: 1,
+            next_window_id: 2,
+            tabs: vec![TabPage {
+                windows: Vec::new(),
+                layout: Layout::Window(1),
+                current_window: 1,
+            }],
+            current_tab: 0,
             cursor: (0, 0),
             scroll_offset: (0, 0),
+            curswant: None,
             terminal_size: (0, 0),
             mode: Mode::Normal,
-            clipboard: String::new(),
+            registers: Registers::new(),
+            pending_register: None,
+            insert_text: String::new(),
+            last_change: None,
+            pending_change: None,
+            recording: None,
+            recorded_keys: Vec::new(),
+            last_macro: None,
+            macro_depth: 0,
             pending_operator: None,
+            pending_prefix: None,
+            count: None,
+            operator_count: None,
+            command_count: None,
+            visual_anchor: (0, 0),
+            block_insert: None,
             command_buffer: String::new(),
             search_query: String::new(),
             last_search_dir: true,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-            file_path: None,
+            search_offset: SearchOffset::None,
+            search_regex: None,
+            search_highlight: true,
+            incsearch_regex: None,
+            search_origin: (0, 0),
+            last_match: None,
+            undo_cursor: (0, 0),
+            last_substitute: None,
+            substitution: None,
+            swap_prompt: None,
+            last_swap_write: Instant::now(),
+            reload_prompt: None,
+            watcher: None,
+            progress: None,
+            global_substitutions: None,
             status_message: String::from("Welcome to RUNE! Press ':' for commands."),
             should_quit: false,
         }
     }
 
-    pub fn handle_key_event(&mut self, event: crossterm::event::KeyEvent) {
-        match self.mode {
-            Mode::Normal => self.handle_normal_mode(event),
-            Mode::Insert => self.handle_insert_mode(event),
-            Mode::Command => self.handle_command_mode(event),
-            _ => {}
+    pub fn handle_key_event(&mut self, event: crossterm::event::KeyEvent) {
+        if self.recording.is_some() && self.macro_depth == 0 {
+            self.recorded_keys.push(event);
+        }
+        if !self.buffer.has_pending_edits() {
+            self.undo_cursor = self.cursor;
+        }
+        // Multi-line messages such as `:ls` go away with the next key.
+        if self.status_message.contains('\n') {
+            self.status_message.clear();
+        }
+        if self.substitution.is_some() {
+            self.handle_substitution_key(event);
+            return;
+        }
+        if self.swap_prompt.is_some() {
+            self.handle_swap_key(event);
+            return;
+        }
+        if self.reload_prompt.is_some() {
+            self.handle_reload_key(event);
+            return;
+        }
+        if self.buffer.hex.is_some()
+            && matches!(self.mode, Mode::Normal | Mode::Insert)
+            && self.handle_hex_key(event)
+        {
+            return;
+        }
+        match self.mode {
+            Mode::Normal => self.handle_normal_mode(event),
+            Mode::Insert => self.handle_insert_mode(event),
+            Mode::Command => self.handle_command_mode(event),
+            Mode::Visual | Mode::VisualLine | Mode::VisualBlock => self.handle_visual_mode(event),
+        }
+        // A change ends when its command (and any Insert session) completes.
+        if self.mode != Mode::Insert {
+            self.commit_undo();
+        }
+        if self.mode != Mode::Insert && self.buffer.hex.is_none() {
+            self.clamp_cursor();
+        }
+    }
+
+    /// Keeps the Normal and Visual mode cursor on the text: a motion or delete
+    /// reaching the end of a final line without a line break leaves it one
+    /// past the last char.
+    fn clamp_cursor(&mut self) {
+        self.cursor.1 = self.cursor.1.min(self.max_col(self.cursor.0));
+    }
+
+    /// Resolves a key into a motion, tracking the `g` prefix of `gg` across
+    /// calls.
+    fn motion_for_key(&mut self, event: crossterm::event::KeyEvent) -> Option<Motion> {
+        if self.pending_prefix.take() == Some('g') {
+            return match event.code {
+                KeyCode::Char('g') => Some(Motion::FileStart),
+                _ => None,
+            };
+        }
+        if event.code == KeyCode::Char('g') {
+            self.pending_prefix = Some('g');
+            return None;
+        }
+        Motion::from_key(event.code)
+    }
+
+    /// Moves the cursor `count` times, or to line `count` for `gg` and `G`.
+    fn apply_motion_count(&mut self, motion: Motion, count: Option<usize>) {
+        // Counts past what a motion could ever use are clamped.
+        let n = i32::try_from(count.unwrap_or(1)).unwrap_or(i32::MAX);
+        match motion {
+            Motion::Left => self.move_cursor(0, -n),
+            Motion::Down => self.move_cursor(n, 0),
+            Motion::Up => self.move_cursor(-n, 0),
+            Motion::Right => self.move_cursor(0, n),
+            Motion::WordForward => self.repeat_word_motion(n, Self::move_to_next_word),
+            Motion::WordBackward => self.repeat_word_motion(n, Self::move_to_prev_word),
+            Motion::LineStart => self.cursor.1 = 0,
+            Motion::LineEnd => {
+                // `N$` moves to the end of the line N-1 lines below.
+                self.move_cursor(n - 1, 0);
+                self.cursor.1 = self.max_col(self.cursor.0);
+                self.curswant = Some((usize::MAX, self.cursor));
+            }
+            Motion::FileStart | Motion::FileEnd => {
+                let last_line = self.last_line();
+                let line = match (motion, count) {
+                    (_, Some(line)) => line.saturating_sub(1).min(last_line),
+                    (Motion::FileStart, None) => 0,
+                    _ => last_line,
+                };
+                self.cursor = (line, 0);
+            }
+        }
+    }
+
+    /// Makes word motion `step` up to `n` times, stopping early once the cursor
+    /// reaches an end of the buffer.
+    fn repeat_word_motion(&mut self, n: i32, step: fn(&mut Self)) {
+        for _ in 0..n {
+            let before = self.cursor;
+            step(self);
+            if self.cursor == before {
+                break;
+            }
+        }
+    }
+
+    /// Accumulates a digit of a count prefix. `0` only extends a pending count;
+    /// on its own it is the line-start motion.
+    fn push_count_digit(&mut self, code: KeyCode) -> bool {
+        let KeyCode::Char(c @ '0'..='9') = code else {
+            return false;
+        };
+        if c == '0' && self.count.is_none() {
+            return false;
+        }
+        let digit = c.to_digit(10).unwrap_or(0) as usize;
+        self.count = Some(
+            self.count
+                .unwrap_or(0)
+                .saturating_mul(10)
+                .saturating_add(digit),
+        );
+        true
+    }
+
+    /// Takes the pending count, multiplying a count typed before an operator
+    /// with the one typed before its motion (`2d3w` deletes six words).
+    fn take_count(&mut self) -> Option<usize> {
+        match (self.operator_count.take(), self.count.take()) {
+            (Some(op_count), Some(count)) => Some(op_count.saturating_mul(count)),
+            (op_count, count) => op_count.or(count),
+        }
+    }
+
+    fn cancel_pending(&mut self) {
+        self.pending_operator = None;
+        self.pending_prefix = None;
+        self.count = None;
+        self.operator_count = None;
+        self.pending_register = None;
+    }
+
+    /// Handles `"{name}` register selection, returning true if the key was
+    /// consumed.
+    fn select_register(&mut self, event: crossterm::event::KeyEvent) -> bool {
+        match (self.pending_prefix, event.code) {
+            (Some('"'), code) => {
+                self.pending_prefix = None;
+                if let KeyCode::Char(name) = code
+                    && Registers::is_valid_name(name)
+                {
+                    self.pending_register = Some(name);
+                }
+                true
+            }
+            (None, KeyCode::Char('"')) => {
+                self.pending_prefix = Some('"');
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads a register, resolving `"%` to the current file name.
+    pub fn read_register(&self, name: char) -> Option<Register> {
+        match name {
+            '%' => self
+                .buffer
+                .path
+                .clone()
+                .map(|path| Register::new(path, RegisterKind::Charwise)),
+            _ => self.registers.get(name),
+        }
+    }
+
+    fn start_insert(&mut self) {
+        self.insert_text.clear();
+        self.mode = Mode::Insert;
+    }
+
+    fn handle_normal_mode(&mut self, event: crossterm::event::KeyEvent) {
+        use crossterm::event::KeyCode;
+
+        if self.pending_prefix.is_none() && self.push_count_digit(event.code) {
+            return;
+        }
+
+        if let Some(op) = self.pending_operator {
+            if event.code == KeyCode::Esc {
+                self.cancel_pending();
+            } else if self.pending_prefix.is_none() && event.code == KeyCode::Char(op) {
+                // Doubled operators (`dd`, `yy`, `cc`) act on whole lines.
+                self.pending_operator = None;
+                let count = self.take_count();
+                self.run_change(Change::new(
+                    op,
+                    ChangeTarget::Lines,
+                    count,
+                    self.pending_register,
+                ));
+            } else if self.awaits_text_object(event) {
+                if let Some(obj) = self.text_object_for_key(event) {
+                    self.pending_operator = None;
+                    let count = self.take_count();
+                    let target = ChangeTarget::TextObject(obj);
+                    self.run_change(Change::new(op, target, count, self.pending_register));
+                } else if self.pending_prefix.is_none() {
+                    self.cancel_pending();
+                }
+            } else if let Some(motion) = self.motion_for_key(event) {
+                self.pending_operator = None;
+                let count = self.take_count();
+                let target = ChangeTarget::Motion(motion);
+                self.run_change(Change::new(op, target, count, self.pending_register));
+            } else if self.pending_prefix.is_none() {
+                self.cancel_pending();
+            }
+            self.scroll();
+            return;
+        }
+
+        if self.select_register(event) {
+            return;
+        }
+
+        if self.pending_prefix == Some(WINDOW_PREFIX) {
+            self.pending_prefix = None;
+            let count = self.take_count();
+            let key = match event.code {
+                KeyCode::Char(c) => Some(c),
+                KeyCode::Left => Some('h'),
+                KeyCode::Down => Some('j'),
+                KeyCode::Up => Some('k'),
+                KeyCode::Right => Some('l'),
+                _ => None,
+            };
+            if let Some(key) = key {
+                self.window_command(key, count);
+            }
+            self.scroll();
+            return;
+        }
+
+        if let Some(prefix @ ('q' | '@' | 'm' | 'Z')) = self.pending_prefix {
+            self.pending_prefix = None;
+            let count = self.take_count();
+            if let KeyCode::Char(name) = event.code {
+                match (prefix, name) {
+                    ('q', _) => self.start_recording(name),
+                    ('@', _) => self.play_macro(name, count.unwrap_or(1)),
+                    ('Z', 'Z') => self.execute_ex("x"),
+                    ('Z', 'Q') => self.execute_ex("q!"),
+                    ('Z', _) => {}
+                    _ if name.is_ascii_lowercase() => {
+                        self.buffer.marks.insert(name, self.cursor);
+                    }
+                    _ => {}
+                }
+            }
+            self.scroll();
+            return;
+        }
+
+        if self.pending_prefix == Some('g')
+            && let KeyCode::Char(key @ ('t' | 'T')) = event.code
+        {
+            self.pending_prefix = None;
+            let count = self.take_count();
+            match (key, count) {
+                ('t', Some(n)) => self.goto_tab(n.saturating_sub(1)),
+                ('t', None) => self.cycle_tab(1),
+                (_, n) => self.cycle_tab(-(n.unwrap_or(1) as isize)),
+            }
+            return;
+        }
+
+        if self.pending_prefix == Some('g')
+            && let KeyCode::Char(dir @ ('-' | '+')) = event.code
+        {
+            self.pending_prefix = None;
+            let steps = isize::try_from(self.take_count().unwrap_or(1)).unwrap_or(isize::MAX);
+            let target = self
+                .buffer
+                .history
+                .step(if dir == '-' { -steps } else { steps });
+            self.goto_undo_state(target);
+            self.scroll();
+            return;
+        }
+
+        if self.pending_prefix.is_some() || event.code == KeyCode::Char('g') {
+            if let Some(motion) = self.motion_for_key(event) {
+                let count = self.take_count();
+                self.apply_motion_count(motion, count);
+            } else if self.pending_prefix.is_none() {
+                self.cancel_pending();
+            }
+            self.scroll();
+            return;
+        }
+
+        let count = self.take_count();
+        if event.code == KeyCode::Char('w') && event.modifiers.contains(KeyModifiers::CONTROL) {
+            self.pending_prefix = Some(WINDOW_PREFIX);
+            self.count = count;
+            return;
+        }
+        if let Some(motion) = Motion::from_key(event.code) {
+            self.apply_motion_count(motion, count);
+            self.scroll();
+            return;
+        }
+
+        match event.code {
+            KeyCode::Char('i') => {
+                self.run_change(Change::new('i', ChangeTarget::Insert, count, None));
+            }
+            KeyCode::Char('q') if self.recording.is_some() => self.stop_recording(),
+            KeyCode::Char('q') => self.pending_prefix = Some('q'),
+            KeyCode::Char('m') => self.pending_prefix = Some('m'),
+            KeyCode::Char('Z') => self.pending_prefix = Some('Z'),
+            KeyCode::Char('&') => self.execute_ex("s"),
+            KeyCode::Char('@') => {
+                self.pending_prefix = Some('@');
+                self.count = count;
+            }
+            KeyCode::Char('v') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.enter_visual(Mode::VisualBlock)
+            }
+            KeyCode::Char('v') => self.enter_visual(Mode::Visual),
+            KeyCode::Char('V') => self.enter_visual(Mode::VisualLine),
+            KeyCode::Char(op @ ('d' | 'y' | 'c')) => {
+                self.pending_operator = Some(op);
+                self.operator_count = count;
+            }
+            KeyCode::Char(key @ ('p' | 'P')) => {
+                let target = ChangeTarget::Paste { before: key == 'P' };
+                self.run_change(Change::new('p', target, count, self.pending_register));
+            }
+            KeyCode::Char('.') => self.repeat_last_change(count),
+            KeyCode::Char(':') => {
+                self.mode = Mode::Command;
+                self.command_buffer = String::from(":");
+                self.command_count = count;
+            }
+            KeyCode::Char(c @ ('/' | '?')) => {
+                self.mode = Mode::Command;
+                self.command_buffer = c.to_string();
+                self.command_count = count;
+                self.search_origin = self.cursor;
+            }
+            KeyCode::Char('n') => self.search(self.last_search_dir, count.unwrap_or(1)),
+            KeyCode::Char('N') => self.search(!self.last_search_dir, count.unwrap_or(1)),
+            KeyCode::Char(key @ ('*' | '#')) => {
+                self.search_word_under_cursor(key == '*', count.unwrap_or(1))
+            }
+            KeyCode::Char('u') => self.undo(count.unwrap_or(1)),
+            KeyCode::Char('r') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.redo(count.unwrap_or(1))
+            }
+            _ => {}
+        }
+        self.scroll();
+    }
+
+    fn enter_visual(&mut self, mode: Mode) {
+        self.visual_anchor = self.cursor;
+        self.mode = mode;
+    }
+
+    fn handle_visual_mode(&mut self, event: crossterm::event::KeyEvent) {
+        use crossterm::event::KeyCode;
+
+        // `'<` and `'>` follow the selection so they survive leaving Visual mode.
+        let (start, end) = self.visual_bounds();
+        self.buffer.marks.insert('<', start);
+        self.buffer.marks.insert('>', end);
+
+        if self.pending_prefix.is_none() && self.push_count_digit(event.code) {
+            return;
+        }
+        if self.select_register(event) {
+            return;
+        }
+
+        if self.pending_prefix.is_none() {
+            let switch_to = match event.code {
+                KeyCode::Char('v') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    Some(Mode::VisualBlock)
+                }
+                KeyCode::Char('v') => Some(Mode::Visual),
+                KeyCode::Char('V') => Some(Mode::VisualLine),
+                _ => None,
+            };
+            if let Some(mode) = switch_to {
+                // Pressing the key of the active kind leaves Visual mode.
+                self.mode = if self.mode == mode {
+                    Mode::Normal
+                } else {
+                    mode
+                };
+                return;
+            }
+
+            match event.code {
+                KeyCode::Esc => {
+                    self.cancel_pending();
+                    self.mode = Mode::Normal;
+                    return;
+                }
+                KeyCode::Char(':') => {
+                    self.mode = Mode::Command;
+                    self.command_buffer = String::from(":'<,'>");
+                    self.count = None;
+                    return;
+                }
+                KeyCode::Char('o') => {
+                    std::mem::swap(&mut self.visual_anchor, &mut self.cursor);
+                    self.scroll();
+                    return;
+                }
+                KeyCode::Char(op @ ('d' | 'y' | 'c' | '>' | '<' | '~')) => {
+                    self.visual_change(op);
+                    self.scroll();
+                    return;
+                }
+                KeyCode::Char('x') => {
+                    self.visual_change('d');
+                    self.scroll();
+                    return;
+                }
+                _ => {}
+            }
+        }
+
+        if self.awaits_text_object(event) {
+            if let Some(obj) = self.text_object_for_key(event) {
+                let count = self.take_count();
+                if let Some(range) = self.text_object_range(obj, count) {
+                    self.select_range(range, obj.is_linewise());
+                }
+            }
+        } else if let Some(motion) = self.motion_for_key(event) {
+            let count = self.take_count();
+            self.apply_motion_count(motion, count);
+        } else if self.pending_prefix.is_none() {
+            self.count = None;
+        }
+        self.scroll();
+    }
+
+    /// Whether `event` starts or completes an `i{key}` / `a{key}` text object.
+    fn awaits_text_object(&self, event: crossterm::event::KeyEvent) -> bool {
+        match self.pending_prefix {
+            Some(prefix) => matches!(prefix, 'i' | 'a'),
+            None => matches!(event.code, KeyCode::Char('i' | 'a')),
+        }
+    }
+
+    /// Resolves `i{key}` / `a{key}` into a text object, tracking the `i`/`a`
+    /// prefix across calls.
+    fn text_object_for_key(&mut self, event: crossterm::event::KeyEvent) -> Option<TextObject> {
+        match (self.pending_prefix.take(), event.code) {
+            (Some(prefix), KeyCode::Char(key)) => TextObject::from_key(prefix == 'a', key),
+            (None, KeyCode::Char(prefix)) => {
+                self.pending_prefix = Some(prefix);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn text_object_range(&self, obj: TextObject, count: Option<usize>) -> Option<Range<usize>> {
+        obj.range(
+            &self.buffer.content,
+            self.cursor_to_char_idx(),
+            count.unwrap_or(1),
+        )
+    }
+
+    /// Makes `range` the current Visual selection.
+    fn select_range(&mut self, range: Range<usize>, linewise: bool) {
+        if range.is_empty() {
+            return;
+        }
+        self.char_idx_to_cursor(range.start);
+        self.visual_anchor = self.cursor;
+        self.char_idx_to_cursor(range.end - 1);
+        if linewise {
+            self.mode = Mode::VisualLine;
+        }
+    }
+
+    /// Returns the Visual-mode anchor and cursor ordered by buffer position.
+    fn visual_bounds(&self) -> ((usize, usize), (usize, usize)) {
+        if self.visual_anchor <= self.cursor {
+            (self.visual_anchor, self.cursor)
+        } else {
+            (self.cursor, self.visual_anchor)
+        }
+    }
+
+    /// Returns the selected columns `[start, end)` on `row` while in a Visual
+    /// mode, for use by the renderer.
+    pub fn visual_cols(&self, row: usize) -> Option<(usize, usize)> {
+        let (start, end) = self.visual_bounds();
+        if !self.mode.is_visual() || row < start.0 || row > end.0 {
+            return None;
+        }
+        let len = self.line_len(row);
+        match self.mode {
+            Mode::VisualLine => Some((0, len)),
+            Mode::VisualBlock => {
+                let left = self.visual_anchor.1.min(self.cursor.1);
+                let right = self.visual_anchor.1.max(self.cursor.1);
+                Some((left, right + 1))
+            }
+            _ => {
+                let from = if row == start.0 { start.1 } else { 0 };
+                let to = if row == end.0 {
+                    self.grapheme_end(end)
+                } else {
+                    len
+                };
+                Some((from, to))
+            }
+        }
+    }
+
+    /// Applies `op` to the current selection, recording its extent so `.` can
+    /// repeat it from another cursor position.
+    fn visual_change(&mut self, op: char) {
+        let (start, end) = self.visual_bounds();
+        let rows = end.0 - start.0;
+        let cols = match self.mode {
+            Mode::VisualLine => 0,
+            Mode::VisualBlock => self.visual_anchor.1.abs_diff(self.cursor.1) + 1,
+            _ if rows == 0 => end.1 - start.1 + 1,
+            _ => end.1 + 1,
+        };
+        let target = ChangeTarget::Visual {
+            mode: self.mode,
+            rows,
+            cols,
+        };
+        self.run_change(Change::new(op, target, None, self.pending_register));
+    }
+
+    /// Executes a change and records it for `.`. A change that starts an Insert
+    /// session is only recorded once the session ends, together with the typed
+    /// text.
+    fn run_change(&mut self, change: Change) {
+        let uses_register = matches!(change.op, 'y' | 'd' | 'c');
+        if (change.op != 'y' && !self.check_modifiable())
+            || (uses_register && !self.check_register_writable(change.register))
+        {
+            if self.mode.is_visual() {
+                self.mode = Mode::Normal;
+            }
+            self.pending_register = None;
+            return;
+        }
+        self.pending_register = change.register;
+        match change.target {
+            ChangeTarget::Motion(motion) => self.execute_operator(change.op, motion, change.count),
+            ChangeTarget::TextObject(obj) => {
+                if let Some(range) = self.text_object_range(obj, change.count) {
+                    self.apply_range_operator(change.op, range, obj.is_linewise());
+                }
+            }
+            ChangeTarget::Lines => {
+                let count = change.count.unwrap_or(1);
+                let last = (self.cursor.0 + count - 1).min(self.last_line());
+                let range = self.line_range(self.cursor.0, last);
+                self.apply_range_operator(change.op, range, true);
+            }
+            ChangeTarget::Visual { mode, rows, cols } => {
+                if !self.mode.is_visual() {
+                    // Replaying: select the same extent starting at the cursor.
+                    self.visual_anchor = self.cursor;
+                    let row = (self.cursor.0 + rows).min(self.last_line());
+                    let col = match mode {
+                        Mode::VisualLine => self.cursor.1,
+                        Mode::Visual if rows > 0 => cols.saturating_sub(1),
+                        _ => self.cursor.1 + cols.saturating_sub(1),
+                    };
+                    self.cursor = (row, col.min(self.line_len(row).saturating_sub(1)));
+                    self.mode = mode;
+                }
+                self.apply_visual_operator(change.op);
+            }
+            ChangeTarget::Insert => {
+                self.save_state();
+                self.start_insert();
+            }
+            ChangeTarget::Paste { before } => self.paste(change.count.unwrap_or(1), before),
+        }
+        self.pending_register = None;
+
+        if change.op == 'y' {
+            return;
+        }
+        if self.mode == Mode::Insert {
+            self.pending_change = Some(change);
+        } else {
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Repeats the last change at the cursor; a new count replaces the
+    /// recorded one.
+    fn repeat_last_change(&mut self, count: Option<usize>) {
+        let Some(mut change) = self.last_change.clone() else {
+            return;
+        };
+        if count.is_some() {
+            change.count = count;
+        }
+        // Like Vim, repeating `"1p` pastes from the next numbered register.
+        if let (ChangeTarget::Paste { .. }, Some(reg @ '1'..='8')) =
+            (&change.target, change.register)
+        {
+            change.register = char::from_digit(reg.to_digit(10).unwrap_or(0) + 1, 10);
+        }
+        let inserted = std::mem::take(&mut change.inserted);
+        self.run_change(change);
+
+        if self.mode == Mode::Insert {
+            for c in inserted.chars() {
+                let code = if c == '\n' {
+                    KeyCode::Enter
+                } else {
+                    KeyCode::Char(c)
+                };
+                self.handle_insert_mode(KeyEvent::new(code, KeyModifiers::NONE));
+            }
+            self.handle_insert_mode(KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE));
+        }
+    }
+
+    fn start_recording(&mut self, name: char) {
+        if !name.is_ascii_alphabetic() {
+            self.status_message = format!("Invalid register for recording: {}", name);
+            return;
+        }
+        self.recording = Some(name);
+        self.recorded_keys.clear();
+    }
+
+    fn stop_recording(&mut self) {
+        let Some(name) = self.recording.take() else {
+            return;
+        };
+        // Drop the `q` that ended the recording.
+        self.recorded_keys.pop();
+        let text = keys::encode(&self.recorded_keys);
+        self.registers.record(name, text);
+        self.recorded_keys.clear();
+    }
+
+    /// Replays register `name` as keystrokes `count` times. `@@` repeats the
+    /// last played macro and `@:` the last command line.
+    fn play_macro(&mut self, name: char, count: usize) {
+        const MAX_MACRO_DEPTH: usize = 100;
+
+        let name = if name == '@' {
+            match self.last_macro {
+                Some(name) => name,
+                None => {
+                    self.status_message = String::from("No previously used register");
+                    return;
+                }
+            }
+        } else {
+            name
+        };
+        if name == ':' {
+            let cmd = format!(":{}", self.registers.last_command);
+            for _ in 0..count {
+                self.execute_command(cmd.clone());
+            }
+            self.last_macro = Some(name);
+            return;
+        }
+        let Some(reg) = self.read_register(name) else {
+            return;
+        };
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            self.status_message = String::from("Macro recursion too deep");
+            return;
+        }
+        self.last_macro = Some(name);
+
+        let events = keys::decode(&reg.text);
+        self.macro_depth += 1;
+        for _ in 0..count {
+            for event in &events {
+                self.handle_key_event(*event);
+                if self.should_quit {
+                    break;
+                }
+            }
+        }
+        self.macro_depth -= 1;
+    }
+
+    fn apply_visual_operator(&mut self, op: char) {
+        let (start, end) = self.visual_bounds();
+        let mode = self.mode;
+        self.mode = Mode::Normal;
+
+        match mode {
+            Mode::VisualBlock => {
+                let left = self.visual_anchor.1.min(self.cursor.1);
+                let right = self.visual_anchor.1.max(self.cursor.1);
+                self.apply_block_operator(op, start.0, end.0, left..right + 1);
+            }
+            Mode::VisualLine => {
+                let range = self.line_range(start.0, end.0);
+                self.apply_range_operator(op, range, true);
+            }
+            _ => {
+                let from = self.pos_to_char_idx(start);
+                let to = self.buffer.content.line_to_char(end.0) + self.grapheme_end(end);
+                let to = to.min(self.buffer.content.len_chars());
+                self.apply_range_operator(op, from..to, false);
+            }
+        }
+    }
+
+    /// Applies `op` to a contiguous char range, as produced by a motion or a
+    /// charwise/linewise Visual selection.
+    fn apply_range_operator(&mut self, op: char, range: Range<usize>, linewise: bool) {
+        let kind = if linewise {
+            RegisterKind::Linewise
+        } else {
+            RegisterKind::Charwise
+        };
+        match op {
+            'y' => {
+                let text = self.register_text(range.clone(), linewise);
+                self.registers
+                    .yank(self.pending_register.take(), Register::new(text, kind));
+                self.char_idx_to_cursor(range.start);
+            }
+            'd' | 'c' => {
+                self.save_state();
+                let text = self.register_text(range.clone(), linewise);
+                self.registers
+                    .delete(self.pending_register.take(), Register::new(text, kind));
+
+                let mut range = range;
+                let ends_with_newline =
+                    range.end > range.start && self.buffer.content.char(range.end - 1) == '\n';
+                if linewise && op == 'c' && ends_with_newline {
+                    // Changing whole lines keeps an empty line to type into.
+                    range.end -= 1;
+                } else if linewise && !ends_with_newline && range.start > 0 {
+                    // Deleting the last lines also removes the preceding line break.
+                    range.start -= 1;
+                }
+                self.buffer.remove(range.clone());
+                let idx = range.start.min(self.buffer.content.len_chars());
+                self.char_idx_to_cursor(idx);
+                if linewise && op == 'd' {
+                    let row = self.cursor.0.min(self.last_line());
+                    self.cursor = (row, self.first_non_blank(row));
+                }
+                if op == 'c' {
+                    self.start_insert();
+                }
+            }
+            '>' | '<' => {
+                let first = self.buffer.content.char_to_line(range.start);
+                let last = self
+                    .buffer
+                    .content
+                    .char_to_line(range.end.saturating_sub(1).max(range.start));
+                self.save_state();
+                self.shift_lines(first, last, op == '>');
+            }
+            '~' => {
+                self.save_state();
+                let toggled = toggle_case(&self.buffer.content.slice(range.clone()).to_string());
+                self.buffer.remove(range.clone());
+                self.buffer.insert(range.start, &toggled);
+                self.char_idx_to_cursor(range.start);
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies `op` column-wise to `cols` on every line from `top` to `bottom`.
+    fn apply_block_operator(&mut self, op: char, top: usize, bottom: usize, cols: Range<usize>) {
+        if op == '>' || op == '<' {
+            self.save_state();
+            self.shift_lines(top, bottom, op == '>');
+            return;
+        }
+        if op != 'y' {
+            self.save_state();
+        }
+
+        let mut pieces = Vec::new();
+        let mut touched_rows = Vec::new();
+        for row in top..=bottom {
+            let len = self.line_len(row);
+            if cols.start >= len {
+                pieces.push(String::new());
+                continue;
+            }
+            touched_rows.push(row);
+            let line_start = self.buffer.content.line_to_char(row);
+            let range = line_start + cols.start..line_start + cols.end.min(len);
+            let text = self.buffer.content.slice(range.clone()).to_string();
+            match op {
+                'd' | 'c' => self.buffer.remove(range),
+                '~' => {
+                    self.buffer.remove(range.clone());
+                    self.buffer.insert(range.start, &toggle_case(&text));
+                }
+                _ => {}
+            }
+            pieces.push(text);
+        }
+
+        let reg = Register::new(pieces.join("\n"), RegisterKind::Blockwise);
+        match op {
+            'y' => self.registers.yank(self.pending_register.take(), reg),
+            'd' | 'c' => self.registers.delete(self.pending_register.take(), reg),
+            _ => {}
+        }
+        self.cursor = (top, cols.start.min(self.line_len(top)));
+        if op == 'c' {
+            self.block_insert = Some(BlockInsert {
+                row: top,
+                col: self.cursor.1,
+                rows: touched_rows.into_iter().filter(|&row| row != top).collect(),
+            });
+            self.start_insert();
+        }
+    }
+
+    /// Indents (`right`) or outdents every line from `first` to `last` by
+    /// `tab_size` columns.
+    fn shift_lines(&mut self, first: usize, last: usize, right: bool) {
+        let width = self.config.tab_size;
+        for row in first..=last {
+            let line_start = self.buffer.content.line_to_char(row);
+            if right {
+                if self.line_len(row) > 0 {
+                    self.buffer.insert(line_start, &" ".repeat(width));
+                }
+            } else {
+                let line = self.buffer.content.line(row);
+                let strip = if line.len_chars() > 0 && line.char(0) == '\t' {
+                    1
+                } else {
+                    line.chars().take(width).take_while(|&c| c == ' ').count()
+                };
+                self.buffer.remove(line_start..line_start + strip);
+            }
+        }
+        self.cursor = (first, self.first_non_blank(first));
+    }
+
+    fn first_non_blank(&self, row: usize) -> usize {
+        if row >= self.buffer.content.len_lines() {
+            return 0;
+        }
+        self.buffer
+            .content
+            .line(row)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count()
+    }
+
+    /// Index of the last line, not counting the empty line ropey reports after
+    /// a trailing line break.
+    fn last_line(&self) -> usize {
+        last_line(&self.buffer.content)
+    }
+
+    /// Char range covering lines `first` to `last` including the final line break.
+    fn line_range(&self, first: usize, last: usize) -> Range<usize> {
+        let from = self.buffer.content.line_to_char(first);
+        let to = if last + 1 < self.buffer.content.len_lines() {
+            self.buffer.content.line_to_char(last + 1)
+        } else {
+            self.buffer.content.len_chars()
+        };
+        from..to
+    }
+
+    /// Text of `range` as stored in a register; linewise text always ends in a
+    /// line break.
+    fn register_text(&self, range: Range<usize>, linewise: bool) -> String {
+        let mut text = self.buffer.content.slice(range).to_string();
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Marks the start of a new change, closing any change still in progress.
+    fn save_state(&mut self) {
+        if self.commit_undo() {
+            self.undo_cursor = self.cursor;
+        }
+    }
+
+    fn commit_undo(&mut self) -> bool {
+        // `:g` runs all of its commands as a single change.
+        if self.global_substitutions.is_some() {
+            return false;
+        }
+        self.buffer.commit(self.undo_cursor, self.cursor)
+    }
+
+    fn undo(&mut self, count: usize) {
+        self.commit_undo();
+        if !self.check_modifiable() {
+            return;
+        }
+        if self.buffer.large {
+            self.status_message = String::from(UNDO_OFF);
+            return;
+        }
+        let mut undone = 0;
+        while undone < count
+            && let Some(cursor) = self.buffer.undo()
+        {
+            self.restore_cursor(cursor);
+            undone += 1;
+        }
+        self.report_undo_state(if undone == 0 {
+            "Already at oldest change"
+        } else {
+            "Undo"
+        });
+    }
+
+    fn redo(&mut self, count: usize) {
+        if !self.check_modifiable() {
+            return;
+        }
+        if self.buffer.large {
+            self.status_message = String::from(UNDO_OFF);
+            return;
+        }
+        let mut redone = 0;
+        while redone < count
+            && let Some(cursor) = self.buffer.redo()
+        {
+            self.restore_cursor(cursor);
+            redone += 1;
+        }
+        self.report_undo_state(if redone == 0 {
+            "Already at newest change"
+        } else {
+            "Redo"
+        });
+    }
+
+    /// Jumps to undo state `target` in creation order (`g-`, `g+`, `:earlier`,
+    /// `:later`), crossing branches of the undo tree.
+    fn goto_undo_state(&mut self, target: usize) {
+        self.commit_undo();
+        if !self.check_modifiable() {
+            return;
+        }
+        if let Some(cursor) = self.buffer.goto_state(target) {
+            self.restore_cursor(cursor);
+        }
+        self.report_undo_state("Moved");
+    }
+
+    fn report_undo_state(&mut self, action: &str) {
+        self.status_message = format!(
+            "{}: change {} of {}",
+            action,
+            self.buffer.history.current(),
+            self.buffer.history.latest()
+        );
+    }
+
+    /// Places the cursor at a position recorded in the undo history, clamped to
+    /// the current text.
+    fn restore_cursor(&mut self, (row, col): (usize, usize)) {
+        let row = row.min(self.buffer.content.len_lines().saturating_sub(1));
+        self.cursor = (row, col.min(self.line_len(row)));
+    }
+
+    fn handle_command_mode(&mut self, event: crossterm::event::KeyEvent) {
+        use crossterm::event::KeyCode;
+        match event.code {
+            KeyCode::Esc => {
+                if search_direction(&self.command_buffer).is_some() {
+                    self.cursor = self.search_origin;
+                    self.incsearch_regex = None;
+                }
+                self.mode = Mode::Normal;
+                self.command_buffer.clear();
+                self.command_count = None;
+            }
+            KeyCode::Enter => {
+                let cmd = self.command_buffer.clone();
+                if search_direction(&self.command_buffer).is_some() {
+                    self.cursor = self.search_origin;
+                    self.incsearch_regex = None;
+                }
+                self.execute_command(cmd);
+                self.mode = Mode::Normal;
+                self.command_buffer.clear();
+                self.command_count = None;
+            }
+            KeyCode::Char(c) => {
+                self.command_buffer.push(c);
+                self.update_incsearch();
+            }
+            KeyCode::Backspace => {
+                if self.command_buffer.len() > 1 {
+                    self.command_buffer.pop();
+                    self.update_incsearch();
+                } else {
+                    if search_direction(&self.command_buffer).is_some() {
+                        self.cursor = self.search_origin;
+                        self.incsearch_regex = None;
+                    }
+                    self.mode = Mode::Normal;
+                    self.command_buffer.clear();
+                }
+            }
+            _ => {}
+        }
+        self.scroll();
+    }
+
+    /// Previews the search being typed: moves the cursor to the first match
+    /// from where the search started and highlights all matches.
+    fn update_incsearch(&mut self) {
+        let Some(forward) = search_direction(&self.command_buffer) else {
+            return;
+        };
+        self.cursor = self.search_origin;
+        self.incsearch_regex = None;
+        // Searching a large file on every keystroke would stall typing.
+        if !self.config.incsearch || self.buffer.large || self.buffer.hex.is_some() {
+            return;
+        }
+        let delimiter = if forward { '/' } else { '?' };
+        let Some((pattern, offset)) = search::parse_query(&self.command_buffer[1..], delimiter)
+        else {
+            return;
+        };
+        if pattern.is_empty() {
+            return;
+        }
+        let Ok(regex) = search::compile(&pattern, self.config.ignorecase, self.config.smartcase)
+        else {
+            return;
+        };
+        let from = self.cursor_to_char_idx();
+        if let Some((m, _)) = search::find(&self.buffer.content, &regex, from, forward) {
+            let target = search::apply_offset(&self.buffer.content, &m, offset);
+            self.char_idx_to_cursor(target);
+        }
+        self.incsearch_regex = Some(regex);
+    }
+
+    fn execute_command(&mut self, cmd: String) {
+        if let Some(ex) = cmd.strip_prefix(':') {
+            if ex.trim().is_empty() {
+                return;
+            }
+            self.registers.last_command = ex.to_string();
+            self.execute_ex(ex);
+        } else if let Some(forward) = search_direction(&cmd)
+            && self.buffer.hex.is_some()
+        {
+            // Offsets mean nothing for bytes, so the query is all pattern.
+            if cmd.len() > 1 {
+                self.search_query = cmd[1..].to_string();
+                self.registers.last_search = self.search_query.clone();
+            }
+            self.last_search_dir = forward;
+            self.hex_search(forward);
+        } else if let Some(forward) = search_direction(&cmd) {
+            let delimiter = if forward { '/' } else { '?' };
+            let Some((pattern, offset)) = search::parse_query(&cmd[1..], delimiter) else {
+                self.status_message = format!("Invalid search offset: {}", cmd);
+                return;
+            };
+            // An empty pattern reuses the last one, so `//e` only changes the offset.
+            if !pattern.is_empty() {
+                self.search_query = pattern;
+                self.compile_search();
+            }
+            self.search_offset = offset;
+            self.last_search_dir = forward;
+            self.last_match = None;
+            self.registers.last_search = self.search_query.clone();
+            self.search_highlight = true;
+            let count = self.command_count.take().unwrap_or(1);
+            self.search(forward, count);
+        }
+    }
+
+    /// Runs one Ex command line (without the leading `:`).
+    fn execute_ex(&mut self, ex: &str) {
+        let (range, rest) = match ex::parse_range(ex) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+        let (name, args) = ex::split_command(rest);
+        let default = match name {
+            "g" | "global" | "v" | "vglobal" => (0, self.last_line()),
+            _ => (self.cursor.0, self.cursor.0),
+        };
+        let (first, last) = match self.resolve_range(&range, default) {
+            Ok(lines) => lines,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+        let parts: Vec<&str> = args.split_whitespace().collect();
+
+        match name {
+            "" if range != ExRange::Default => self.cursor = (last, self.first_non_blank(last)),
+            "s" | "substitute" | "&" => {
+                if self.check_modifiable() {
+                    self.substitute(first, last, args);
+                }
+            }
+            "g" | "global" => self.global(first, last, args, false),
+            "v" | "vglobal" => self.global(first, last, args, true),
+            "d" | "delete" | "y" | "yank" => {
+                if name.starts_with('d') && !self.check_modifiable() {
+                    return;
+                }
+                let (register, count) = match ex::parse_register_count(args) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        self.status_message = e;
+                        return;
+                    }
+                };
+                // A count takes that many lines from the end of the range.
+                let (first, last) = match count {
+                    Some(n) => (last, last.saturating_add(n - 1).min(self.last_line())),
+                    None => (first, last),
+                };
+                if !self.check_register_writable(register) {
+                    return;
+                }
+                self.pending_register = register;
+                let cursor = self.cursor;
+                let range = self.line_range(first, last);
+                self.apply_range_operator(
+                    if name.starts_with('d') { 'd' } else { 'y' },
+                    range,
+                    true,
+                );
+                if name.starts_with('y') {
+                    self.cursor = cursor;
+                }
+            }
+            "p" | "print" => {
+                let (line, _) = search::line_text(&self.buffer.content, last);
+                self.status_message = line.to_string();
+            }
+            "q" | "quit" => self.quit_window(args.starts_with('!')),
+            "qa" | "qall" | "quita" | "quitall" => self.quit_all(args.starts_with('!')),
+            "w" => {
+                let (force, args) = match args.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, args),
+                };
+                let (options, path) = match ex::parse_file_options(args) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        self.status_message = e;
+                        return;
+                    }
+                };
+                // The buffer takes on the new format, keeping it if the
+                // write fails.
+                let previous = (self.buffer.fileformat, self.buffer.encoding);
+                self.buffer.fileformat = options.fileformat.unwrap_or(previous.0);
+                self.buffer.encoding = options.encoding.unwrap_or(previous.1);
+                let path = path.trim();
+                let path = (!path.is_empty()).then(|| path.to_string());
+                if !self.save_file(path, force) {
+                    (self.buffer.fileformat, self.buffer.encoding) = previous;
+                }
+            }
+            "wq" => {
+                if self.save_file(None, args.starts_with('!')) {
+                    self.quit_window(false);
+                }
+            }
+            "x" | "xit" | "exi" | "exit" => {
+                if !self.buffer.modified() || self.save_file(None, args.starts_with('!')) {
+                    self.quit_window(false);
+                }
+            }
+            "wa" | "wall" => self.write_all(),
+            "follow" => self.toggle_follow(),
+            "hex" => self.toggle_hex(),
+            "checkt" | "checktime" => {
+                let ids = self.buffer_ids();
+                self.check_time(&ids);
+            }
+            "sp" | "split" => self.split_window(SplitDir::Horizontal, args.trim()),
+            "vs" | "vsplit" => self.split_window(SplitDir::Vertical, args.trim()),
+            "clo" | "close" => self.close_window(self.current_window),
+            "on" | "only" => self.only_window(),
+            "tabnew" | "tabe" | "tabedit" => self.new_tab(args.trim()),
+            "tabn" | "tabnext" => match args.trim() {
+                "" => self.cycle_tab(1),
+                arg => {
+                    if let Some(idx) = self.tab_arg(arg) {
+                        self.goto_tab(idx);
+                    }
+                }
+            },
+            "tabp" | "tabprevious" | "tabN" | "tabNext" => {
+                let steps = args.trim().parse::<isize>().unwrap_or(1);
+                self.cycle_tab(-steps);
+            }
+            "tabc" | "tabclose" => {
+                if let Some(idx) = self.tab_arg(args.trim()) {
+                    self.close_tab(idx);
+                }
+            }
+            "tabo" | "tabonly" => self.only_tab(),
+            "res" | "resize" => self.resize_command(SplitDir::Horizontal, args.trim()),
+            "vert" | "vertical" => match ex::split_command(args) {
+                ("res" | "resize", size) => self.resize_command(SplitDir::Vertical, size.trim()),
+                ("sp" | "split" | "new", path) => {
+                    self.split_window(SplitDir::Vertical, path.trim())
+                }
+                _ => self.status_message = format!("Not an editor command: {}", ex),
+            },
+            "e" | "edit" => self.edit(args),
+            "vie" | "view" => self.view(args),
+            "ls" | "buffers" | "files" => self.list_buffers(),
+            "bn" | "bnext" | "bp" | "bprevious" | "bN" | "bNext" => {
+                let steps = args.trim().parse::<isize>().unwrap_or(1);
+                self.cycle_buffer(if name.starts_with("bn") {
+                    steps
+                } else {
+                    -steps
+                });
+            }
+            "b" | "buffer" => match self.find_buffer(args.trim()) {
+                Ok(id) => self.switch_buffer(id),
+                Err(e) => self.status_message = e,
+            },
+            "bd" | "bdelete" => self.delete_buffer(args),
+            "set" | "se" => parts.iter().for_each(|arg| self.set_option(arg)),
+            "nohlsearch" | "noh" => self.search_highlight = false,
+            "earlier" | "ea" | "later" | "lat" => {
+                let earlier = name.starts_with('e');
+                match parse_undo_offset(parts.first().copied().unwrap_or("1")) {
+                    Some(UndoOffset::Steps(steps)) => {
+                        let steps = isize::try_from(steps).unwrap_or(isize::MAX);
+                        let target = self
+                            .buffer
+                            .history
+                            .step(if earlier { -steps } else { steps });
+                        self.goto_undo_state(target);
+                    }
+                    Some(UndoOffset::Time(offset)) => {
+                        let target = self.buffer.history.state_at(offset, earlier);
+                        self.goto_undo_state(target);
+                    }
+                    None => self.status_message = format!("Invalid argument: {}", ex),
+                }
+            }
+            _ => self.status_message = format!("Not an editor command: {}", ex),
+        }
+    }
+
+    /// Resolves an Ex range to 0-based lines, `default` when none was given.
+    fn resolve_range(
+        &mut self,
+        range: &ExRange,
+        default: (usize, usize),
+    ) -> Result<(usize, usize), String> {
+        let current = self.cursor.0;
+        match range {
+            ExRange::Default => Ok(default),
+            ExRange::All => Ok((0, self.last_line())),
+            ExRange::Line(spec) => {
+                let row = self.resolve_address(spec, current)?;
+                Ok((row, row))
+            }
+            ExRange::Span {
+                start,
+                end,
+                set_current,
+            } => {
+                let first = self.resolve_address(start, current)?;
+                let last = self.resolve_address(end, if *set_current { first } else { current })?;
+                Ok((first.min(last), first.max(last)))
+            }
+        }
+    }
+
+    fn resolve_address(&mut self, spec: &LineSpec, current: usize) -> Result<usize, String> {
+        let base = match &spec.base {
+            Address::Line(n) => n.saturating_sub(1),
+            Address::Current => current,
+            Address::Last => self.last_line(),
+            Address::Mark(name) => match self.buffer.marks.get(name) {
+                Some(&(row, _)) => row.min(self.last_line()),
+                None => return Err(format!("Mark not set: '{}", name)),
+            },
+            Address::Pattern { pattern, forward } => {
+                let regex = self.ex_pattern(pattern, None)?;
+                let lines = self.last_line() + 1;
+                (1..=lines)
+                    .map(|step| {
+                        if *forward {
+                            (current + step) % lines
+                        } else {
+                            (current + lines - step % lines) % lines
+                        }
+                    })
+                    .find(|&row| regex.is_match(&search::line_text(&self.buffer.content, row).1))
+                    .ok_or_else(|| format!("Pattern not found: {}", self.search_query))?
+            }
+        };
+        let row = base as isize + spec.offset;
+        if row < 0 || row as usize > self.last_line() {
+            return Err(String::from("Invalid range"));
+        }
+        Ok(row as usize)
+    }
+
+    /// Regex for a pattern in an Ex command, which also becomes the last search
+    /// pattern. An empty pattern reuses the last search; `ignorecase`
+    /// overrides the option when set.
+    fn ex_pattern(&mut self, pattern: &str, ignorecase: Option<bool>) -> Result<Regex, String> {
+        if !pattern.is_empty() {
+            self.search_query = pattern.to_string();
+            self.registers.last_search = self.search_query.clone();
+            self.last_match = None;
+            self.compile_search();
+        }
+        if self.search_query.is_empty() {
+            return Err(String::from("No previous regular expression"));
+        }
+        self.search_highlight = true;
+        let regex = match ignorecase {
+            Some(ignore) => search::compile(&self.search_query, ignore, false).ok(),
+            None => self.search_regex.clone(),
+        };
+        regex.ok_or_else(|| format!("Invalid pattern: {}", self.search_query))
+    }
+
+    /// `:s/pat/rep/flags` on lines `first..=last`. Without a pattern (`:s`,
+    /// `:&`, `&`) the last substitution is repeated.
+    fn substitute(&mut self, first: usize, last: usize, args: &str) {
+        let (pattern, replacement, flags) = match ex::parse_substitute(args) {
+            Some(parsed) => parsed,
+            None => match &self.last_substitute {
+                Some(prev) => (prev.pattern.clone(), prev.replacement.clone(), args),
+                None => {
+                    self.status_message = String::from("No previous substitute regular expression");
+                    return;
+                }
+            },
+        };
+        let previous = self.last_substitute.as_ref().map(|prev| prev.flags);
+        let (flags, count) = match ex::SubstituteFlags::parse(flags, previous.unwrap_or_default()) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+        if flags.confirm && self.global_substitutions.is_some() {
+            self.status_message = String::from("Cannot confirm substitutions inside :g");
+            return;
+        }
+        // A count makes the range start at its last line.
+        let (first, last) = match count {
+            Some(count) => (last, (last + count - 1).min(self.last_line())),
+            None => (first, last),
+        };
+        let regex = match self.ex_pattern(&pattern, flags.ignorecase) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+        self.last_substitute = Some(LastSubstitute {
+            pattern: self.search_query.clone(),
+            replacement: replacement.clone(),
+            flags,
+        });
+
+        let mut sub = Substitution::new(regex, replacement, flags.global, first, last);
+        if flags.confirm {
+            self.prompt_substitution(sub);
+            return;
+        }
+        while let Some((row, m)) = sub.next_match(&self.buffer.content) {
+            sub.replace(&mut self.buffer, row, m);
+        }
+        self.finish_substitution(sub);
+    }
+
+    /// Moves to the next match of a `:s///c` and asks what to do with it, or
+    /// finishes the substitution when none are left.
+    fn prompt_substitution(&mut self, mut sub: Substitution) {
+        match sub.next_match(&self.buffer.content) {
+            Some((row, m)) => {
+                self.cursor = (row, m.start);
+                self.status_message = format!("replace with {} (y/n/a/q/l)?", sub.replacement);
+                self.substitution = Some(sub);
+            }
+            None => self.finish_substitution(sub),
+        }
+        self.scroll();
+    }
+
+    fn handle_substitution_key(&mut self, event: KeyEvent) {
+        let Some(mut sub) = self.substitution.take() else {
+            return;
+        };
+        let Some((row, m)) = sub.next_match(&self.buffer.content) else {
+            self.finish_substitution(sub);
+            return;
+        };
+        match event.code {
+            KeyCode::Char('y') => sub.replace(&mut self.buffer, row, m),
+            KeyCode::Char('n') => sub.skip(m),
+            KeyCode::Char('a') => {
+                sub.replace(&mut self.buffer, row, m);
+                while let Some((row, m)) = sub.next_match(&self.buffer.content) {
+                    sub.replace(&mut self.buffer, row, m);
+                }
+            }
+            KeyCode::Char('l') => {
+                sub.replace(&mut self.buffer, row, m);
+                self.finish_substitution(sub);
+                return;
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.finish_substitution(sub);
+                return;
+            }
+            _ => {
+                self.substitution = Some(sub);
+                return;
+            }
+        }
+        self.prompt_substitution(sub);
+    }
+
+    /// Reports a finished `:s` and leaves the cursor on the last changed line.
+    fn finish_substitution(&mut self, sub: Substitution) {
+        if let Some(row) = sub.last_row {
+            self.cursor = (row, self.first_non_blank(row));
+            self.scroll();
+        }
+        if let Some((count, lines)) = &mut self.global_substitutions {
+            *count += sub.count;
+            *lines += sub.lines;
+            return;
+        }
+        self.commit_undo();
+        self.status_message = if sub.count > 0 {
+            format!(
+                "{} on {}",
+                plural(sub.count, "substitution"),
+                plural(sub.lines, "line")
+            )
+        } else {
+            format!("Pattern not found: {}", self.search_query)
+        };
+    }
+
+    /// `:g/pat/cmd` runs Ex command `cmd` (default `p`) on every line in
+    /// `first..=last` matching `pat`, or with `invert` (`:v`, `:g!`) on every
+    /// line not matching, as one change.
+    fn global(&mut self, first: usize, last: usize, args: &str, mut invert: bool) {
+        if self.global_substitutions.is_some() {
+            self.status_message = String::from("Cannot use :g recursively");
+            return;
+        }
+        let args = match args.strip_prefix('!') {
+            Some(rest) => {
+                invert = true;
+                rest
+            }
+            None => args.trim_start(),
+        };
+        let Some(delimiter) = args
+            .chars()
+            .next()
+            .filter(|c| !c.is_alphanumeric() && !matches!(c, '\\' | '"' | '|'))
+        else {
+            self.status_message = String::from("Regular expression missing from :g");
+            return;
+        };
+        let (pattern, command) = search::split_delimited(&args[delimiter.len_utf8()..], delimiter);
+        let command = command
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .unwrap_or("p");
+        let regex = match self.ex_pattern(&pattern, None) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.status_message = e;
+                return;
+            }
+        };
+
+        // Mark every line first so the command can add and remove lines.
+        let mut anchors: Vec<Option<LineAnchor>> = (first..=last)
+            .filter(|&row| {
+                let (_, line) = search::line_text(&self.buffer.content, row);
+                regex.is_match(&line) != invert
+            })
+            .map(|row| Some(LineAnchor::new(&self.buffer.content, row)))
+            .collect();
+        if anchors.is_empty() {
+            self.status_message = if invert {
+                format!("Pattern found in every line: {}", self.search_query)
+            } else {
+                format!("Pattern not found: {}", self.search_query)
+            };
+            return;
+        }
+
+        self.global_substitutions = Some((0, 0));
+        self.status_message.clear();
+        for i in 0..anchors.len() {
+            let Some(anchor) = anchors[i] else {
+                continue;
+            };
+            let start = anchor.start.min(self.buffer.content.len_chars());
+            self.cursor = (self.buffer.content.char_to_line(start), 0);
+            let mark = self.buffer.edit_mark();
+            self.execute_ex(command);
+
+            let edits = self.buffer.edits_since(mark);
+            let growth: isize = edits
+                .iter()
+                .map(|&(_, removed, inserted)| inserted as isize - removed as isize)
+                .sum();
+            let mut len = (self.buffer.content.len_chars() as isize - growth) as usize;
+            for (pos, removed, inserted) in edits {
+                for anchor in &mut anchors[i + 1..] {
+                    *anchor = anchor.and_then(|a| a.shift(pos, removed, inserted, len));
+                }
+                len = len - removed + inserted;
+            }
+        }
+
+        let (count, lines) = self.global_substitutions.take().unwrap_or_default();
+        if count > 0 {
+            self.status_message = format!(
+                "{} on {}",
+                plural(count, "substitution"),
+                plural(lines, "line")
+            );
+        }
+        let row = self.cursor.0.min(self.last_line());
+        self.cursor = (row, self.cursor.1.min(self.line_len(row)));
+    }
+
+    /// Opens `path` in a buffer, or switches to the buffer already showing
+    /// it. A file that does not exist yet gets an empty buffer bound to the
+    /// path, created on the first write.
+    pub fn open_file(&mut self, path: &str) -> Result<(), OpenError> {
+        if self.buffer.path.as_deref() == Some(path) {
+            return Ok(());
+        }
+        if let Some(id) = self
+            .buffers
+            .iter()
+            .find(|b| b.path.as_deref() == Some(path))
+            .map(|b| b.id)
+        {
+            self.switch_buffer(id);
+            return Ok(());
+        }
+        let threshold = self.config.large_file_threshold;
+        let buffer = match load_buffer(path, threshold, self.progress.as_deref()) {
+            Ok(buffer) => buffer,
+            Err(OpenError::Io(_, e)) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut buffer = Buffer::new();
+                buffer.path = Some(path.to_string());
+                self.push_buffer(buffer);
+                self.status_message = format!("\"{}\" [New]", path);
+                if let Some(watcher) = &mut self.watcher {
+                    watcher.watch(path);
+                }
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        self.push_buffer(buffer);
+        if let Some(watcher) = &mut self.watcher {
+            watcher.watch(path);
+        }
+        if !fileio::is_writable(path) {
+            self.buffer.readonly = true;
+            self.status_message = format!("\"{}\" [readonly]", path);
+        }
+        if self.buffer.is_binary() {
+            self.status_message = format!("\"{}\" [binary] (:hex to edit its bytes)", path);
+        } else if self.buffer.large {
+            self.status_message = format!("\"{}\" [large file]", path);
+        }
+
+        if self.config.undo_file
+            && !self.buffer.large
+            && let Some(history) =
+                undofile::load(&self.config.undo_dir(), path, &self.buffer.content)
+        {
+            self.buffer.history = history;
+            self.buffer.mark_saved();
+            self.status_message = format!("Loaded undo history for {}", path);
+        }
+        self.check_swap_file();
+        Ok(())
+    }
+
+    /// Opens `path`, reporting failure in the status line.
+    fn open_file_or_report(&mut self, path: &str) {
+        if let Err(e) = self.open_file(path) {
+            self.status_message = e.to_string();
+        }
+    }
+
+    /// `:e[!] [path]`. Leaving a modified buffer needs `!`, which throws its
+    /// changes away; without a path `:e!` re-reads the current file.
+    fn edit(&mut self, args: &str) {
+        let (force, path) = match args.strip_prefix('!') {
+            Some(rest) => (true, rest.trim()),
+            None => (false, args.trim()),
+        };
+        if path.is_empty() || self.buffer.path.as_deref() == Some(path) {
+            if force {
+                self.revert_buffer();
+            }
+            return;
+        }
+        if self.buffer.modified() && !force {
+            self.status_message = String::from(NOT_SAVED);
+            return;
+        }
+        if self.buffer.modified() {
+            self.revert_buffer();
+        }
+        self.open_file_or_report(path);
+    }
+
+    /// `:view [path]`: `:e`, leaving the buffer read-only.
+    fn view(&mut self, args: &str) {
+        self.edit(args);
+        let path = args.trim_start_matches('!').trim();
+        if path.is_empty() || self.buffer.path.as_deref() == Some(path) {
+            self.buffer.readonly = true;
+        }
+    }
+
+    /// Whether yanked or deleted text may go to register `name`, showing an
+    /// error for the read-only ones.
+    fn check_register_writable(&mut self, name: Option<char>) -> bool {
+        match name {
+            Some(name) if !Registers::is_writable(name) => {
+                self.status_message = format!("E354: Invalid register name: '{}'", name);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether the current buffer's text may be changed; says why not
+    /// otherwise.
+    fn check_modifiable(&mut self) -> bool {
+        let message = if self.buffer.hex.is_some() {
+            Some(HEX_ONLY)
+        } else {
+            self.lock_message()
+        };
+        match message {
+            Some(message) => {
+                self.status_message = String::from(message);
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Why the current buffer cannot be changed at all, if it cannot.
+    fn lock_message(&self) -> Option<&'static str> {
+        if !self.buffer.modifiable {
+            Some(NOT_MODIFIABLE)
+        } else if self.buffer.readonly {
+            Some(READONLY_CHANGE)
+        } else {
+            None
+        }
+    }
+
+    /// Drops unsaved changes by re-reading the buffer's file, or emptying it
+    /// when it has none.
+    fn revert_buffer(&mut self) {
+        let threshold = self.config.large_file_threshold;
+        Self::read_back(&mut self.buffer, threshold, self.progress.as_deref());
+        self.restore_cursor(self.cursor);
+    }
+
+    /// Replaces `buffer` with a fresh copy of its file, keeping its number
+    /// and settings but not its undo history.
+    fn read_back(buffer: &mut Buffer, threshold: u64, progress: Option<&Progress>) {
+        let mut fresh = buffer
+            .path
+            .as_deref()
+            .and_then(|path| load_buffer(path, threshold, progress).ok())
+            .unwrap_or_else(Buffer::new);
+        fresh.id = buffer.id;
+        fresh.path = buffer.path.take();
+        // Following stops, as the file is read again from the start.
+        fresh.readonly = buffer
+            .follow
+            .as_ref()
+            .map_or(buffer.readonly, |follow| follow.was_readonly);
+        fresh.modifiable = buffer.modifiable;
+        fresh.swap_path = buffer.swap_path.take();
+        fresh.cursor = buffer.cursor;
+        fresh.scroll_offset = buffer.scroll_offset;
+        *buffer = fresh;
+    }
+
+    /// Makes `buffer` the current one under a new number. The previous buffer
+    /// stays listed unless it is an untouched `[No Name]` buffer.
+    fn push_buffer(&mut self, mut buffer: Buffer) {
+        self.commit_undo();
+        if self.buffer.path.is_none()
+            && !self.buffer.modified()
+            && self.buffer.content.len_chars() == 0
+        {
+            buffer.id = self.buffer.id;
+            self.buffer = buffer;
+        } else {
+            buffer.id = self.next_buffer_id;
+            self.next_buffer_id += 1;
+            self.buffer.cursor = self.cursor;
+            self.buffer.scroll_offset = self.scroll_offset;
+            let previous = std::mem::replace(&mut self.buffer, buffer);
+            self.list_buffer(previous);
+        }
+        self.cursor = (0, 0);
+        self.scroll_offset = (0, 0);
+        self.last_match = None;
+    }
+
+    /// Makes the listed buffer `id` current, remembering where the cursor was
+    /// in the one it replaces.
+    fn switch_buffer(&mut self, id: usize) {
+        if id == self.buffer.id {
+            return;
+        }
+        self.buffer.cursor = self.cursor;
+        self.buffer.scroll_offset = self.scroll_offset;
+        if !self.activate_buffer(id) {
+            self.status_message = format!("Buffer {} does not exist", id);
+            return;
+        }
+        self.show_buffer();
+    }
+
+    /// Swaps the listed buffer `id` in as the current buffer.
+    fn activate_buffer(&mut self, id: usize) -> bool {
+        let Some(idx) = self.buffers.iter().position(|b| b.id == id) else {
+            return false;
+        };
+        self.commit_undo();
+        let next = self.buffers.remove(idx);
+        let previous = std::mem::replace(&mut self.buffer, next);
+        self.list_buffer(previous);
+        true
+    }
+
+    /// Restores the current buffer's cursor and viewport after a switch.
+    fn show_buffer(&mut self) {
+        self.restore_cursor(self.buffer.cursor);
+        self.scroll_offset = self.buffer.scroll_offset;
+        self.last_match = None;
+        self.scroll();
+        self.status_message = format!(
+            "\"{}\"{} {} lines",
+            self.buffer.name(),
+            if self.buffer.modified() { " [+]" } else { "" },
+            self.last_line() + 1
+        );
+    }
+
+    fn list_buffer(&mut self, buffer: Buffer) {
+        let at = self.buffers.partition_point(|b| b.id < buffer.id);
+        self.buffers.insert(at, buffer);
+    }
+
+    /// Numbers of all listed buffers, in order.
+    fn buffer_ids(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = self.buffers.iter().map(|b| b.id).collect();
+        ids.push(self.buffer.id);
+        ids.sort_unstable();
+        ids
+    }
+
+    /// `:bn` / `:bp`: moves `steps` buffers along the list, wrapping around.
+    fn cycle_buffer(&mut self, steps: isize) {
+        let ids = self.buffer_ids();
+        let len = ids.len() as isize;
+        let pos = ids.iter().position(|&id| id == self.buffer.id).unwrap_or(0) as isize;
+        self.switch_buffer(ids[(pos + steps).rem_euclid(len) as usize]);
+    }
+
+    /// Resolves a `:b` / `:bd` argument: a buffer number or a unique part of a
+    /// file name.
+    fn find_buffer(&self, arg: &str) -> Result<usize, String> {
+        let all = || std::iter::once(&self.buffer).chain(&self.buffers);
+        if let Ok(id) = arg.parse::<usize>() {
+            return all()
+                .any(|b| b.id == id)
+                .then_some(id)
+                .ok_or_else(|| format!("Buffer {} does not exist", id));
+        }
+        let matches: Vec<usize> = all()
+            .filter(|b| b.path.as_deref().is_some_and(|path| path.contains(arg)))
+            .map(|b| b.id)
+            .collect();
+        match matches[..] {
+            [id] => Ok(id),
+            [] => Err(format!("No matching buffer for {}", arg)),
+            _ => Err(format!("More than one match for {}", arg)),
+        }
+    }
+
+    /// `:ls`: one line per buffer with its number, `%a` for the current one,
+    /// `+` when modified, and the line the cursor is on.
+    fn list_buffers(&mut self) {
+        let mut buffers: Vec<&Buffer> =
+            std::iter::once(&self.buffer).chain(&self.buffers).collect();
+        buffers.sort_by_key(|b| b.id);
+        let lines: Vec<String> = buffers
+            .iter()
+            .map(|b| {
+                let current = b.id == self.buffer.id;
+                let row = if current { self.cursor.0 } else { b.cursor.0 };
+                format!(
+                    "{:>3} {} {} \"{}\" line {}",
+                    b.id,
+                    if current { "%a" } else { "  " },
+                    if b.modified() { "+" } else { " " },
+                    b.name(),
+                    row + 1
+                )
+            })
+            .collect();
+        self.status_message = lines.join("\n");
+    }
+
+    /// `:bd[!] [N]`: removes a buffer from the list. A modified buffer needs
+    /// `!`. Deleting the current buffer shows the next one, or a new empty
+    /// buffer when it was the last.
+    fn delete_buffer(&mut self, args: &str) {
+        let (force, arg) = match args.strip_prefix('!') {
+            Some(rest) => (true, rest.trim()),
+            None => (false, args.trim()),
+        };
+        let id = if arg.is_empty() {
+            self.buffer.id
+        } else {
+            match self.find_buffer(arg) {
+                Ok(id) => id,
+                Err(e) => {
+                    self.status_message = e;
+                    return;
+                }
+            }
+        };
+        let modified = std::iter::once(&self.buffer)
+            .chain(&self.buffers)
+            .any(|b| b.id == id && b.modified());
+        if modified && !force {
+            self.status_message = format!(
+                "No write since last change for buffer {} (add ! to override)",
+                id
+            );
+            return;
+        }
+
+        if let Some(swap_path) = self.buffer_by_id(id).and_then(|b| b.swap_path.clone()) {
+            let _ = std::fs::remove_file(swap_path);
+        }
+
+        // Other windows showing the buffer close with it.
+        let showing: Vec<usize> = self
+            .windows
+            .iter()
+            .filter(|w| w.id != self.current_window && w.buffer_id == id)
+            .map(|w| w.id)
+            .collect();
+        for window in showing {
+            self.layout.remove(window);
+            self.windows.retain(|w| w.id != window);
+        }
+        // Tab pages left without a window close too.
+        let mut i = 0;
+        while i < self.tabs.len() {
+            if i != self.current_tab && !self.tabs[i].close_buffer(id) {
+                self.tabs.remove(i);
+                if i < self.current_tab {
+                    self.current_tab -= 1;
+                }
+            } else {
+                i += 1;
+            }
+        }
+
+        if id != self.buffer.id {
+            self.buffers.retain(|b| b.id != id);
+        } else if self.buffers.is_empty() {
+            let mut buffer = Buffer::new();
+            buffer.id = self.next_buffer_id;
+            self.next_buffer_id += 1;
+            self.buffer = buffer;
+            self.cursor = (0, 0);
+            self.scroll_offset = (0, 0);
+        } else {
+            let idx = self
+                .buffers
+                .partition_point(|b| b.id < id)
+                .min(self.buffers.len() - 1);
+            self.buffer = self.buffers.remove(idx);
+            self.show_buffer();
+        }
+    }
+
+    /// Screen area shared by the windows: everything between the tab line
+    /// and the command line.
+    pub fn screen_area(&self) -> Rect {
+        let top = self.has_tab_line() as u16;
+        Rect {
+            x: 0,
+            y: top,
+            width: self.terminal_size.0,
+            height: self.terminal_size.1.saturating_sub(1 + top),
+        }
+    }
+
+    /// The tab line is shown once there is more than one tab page.
+    pub fn has_tab_line(&self) -> bool {
+        self.tabs.len() > 1
+    }
+
+    /// The buffer in the focused window of each tab page.
+    pub fn tab_buffers(&self) -> Vec<&Buffer> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tab)| {
+                if i == self.current_tab {
+                    Some(&self.buffer)
+                } else {
+                    tab.current().and_then(|w| self.buffer_by_id(w.buffer_id))
+                }
+            })
+            .collect()
+    }
+
+    pub fn window_rects(&self) -> Vec<(usize, Rect)> {
+        self.layout.rects(self.screen_area())
+    }
+
+    pub fn current_rect(&self) -> Rect {
+        self.window_rects()
+            .into_iter()
+            .find(|&(id, _)| id == self.current_window)
+            .map_or_else(|| self.screen_area(), |(_, rect)| rect)
+    }
+
+    /// Windows get their own status line once the screen is split.
+    pub fn has_splits(&self) -> bool {
+        self.windows.len() > 1
+    }
+
+    pub fn window(&self, id: usize) -> Option<&Window> {
+        self.windows.iter().find(|w| w.id == id)
+    }
+
+    pub fn buffer_by_id(&self, id: usize) -> Option<&Buffer> {
+        std::iter::once(&self.buffer)
+            .chain(&self.buffers)
+            .find(|b| b.id == id)
+    }
+
+    /// Stores the live cursor and viewport in the current window.
+    fn save_window(&mut self) {
+        let (buffer_id, cursor, scroll_offset) = (self.buffer.id, self.cursor, self.scroll_offset);
+        if let Some(window) = self
+            .windows
+            .iter_mut()
+            .find(|w| w.id == self.current_window)
+        {
+            window.buffer_id = buffer_id;
+            window.cursor = cursor;
+            window.scroll_offset = scroll_offset;
+        }
+    }
+
+    fn switch_window(&mut self, id: usize) {
+        if id == self.current_window {
+            return;
+        }
+        let Some(target) = self.window(id).cloned() else {
+            return;
+        };
+        self.save_window();
+        self.enter_window(target);
+    }
+
+    /// Focuses `window`, bringing in its buffer, cursor and viewport.
+    fn enter_window(&mut self, window: Window) {
+        self.buffer.cursor = self.cursor;
+        self.buffer.scroll_offset = self.scroll_offset;
+        self.current_window = window.id;
+        if window.buffer_id != self.buffer.id {
+            self.activate_buffer(window.buffer_id);
+        }
+        self.restore_cursor(window.cursor);
+        self.scroll_offset = window.scroll_offset;
+        self.last_match = None;
+        self.scroll();
+    }
+
+    /// Saves the current tab page's windows into `tabs`.
+    fn save_tab(&mut self) {
+        self.save_window();
+        self.tabs[self.current_tab] = TabPage {
+            windows: self.windows.clone(),
+            layout: self.layout.clone(),
+            current_window: self.current_window,
+        };
+    }
+
+    /// Makes tab page `idx` (counting from 0) current.
+    fn goto_tab(&mut self, idx: usize) {
+        if idx >= self.tabs.len() || idx == self.current_tab {
+            return;
+        }
+        self.save_tab();
+        let tab = self.tabs[idx].clone();
+        self.current_tab = idx;
+        self.windows = tab.windows;
+        self.layout = tab.layout;
+        if let Some(window) = self.window(tab.current_window).cloned() {
+            self.enter_window(window);
+        }
+    }
+
+    /// `gt` / `gT`: moves `steps` tab pages forward or back, wrapping around.
+    fn cycle_tab(&mut self, steps: isize) {
+        let len = self.tabs.len() as isize;
+        let idx = (self.current_tab as isize + steps).rem_euclid(len);
+        self.goto_tab(idx as usize);
+    }
+
+    /// `:tabnew [path]`: opens a tab page after the current one with a single
+    /// window on `path`, or on a new empty buffer.
+    fn new_tab(&mut self, path: &str) {
+        self.save_tab();
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.windows = vec![Window {
+            id,
+            buffer_id: self.buffer.id,
+            cursor: self.cursor,
+            scroll_offset: self.scroll_offset,
+        }];
+        self.layout = Layout::Window(id);
+        self.current_window = id;
+        self.current_tab += 1;
+        self.tabs
+            .insert(self.current_tab, self.tabs[self.current_tab - 1].clone());
+        if path.is_empty() {
+            self.push_buffer(Buffer::new());
+        } else {
+            self.open_file_or_report(path);
+        }
+        self.scroll();
+    }
+
+    /// `:tabclose [N]`: closes tab page `idx`. Its buffers stay listed.
+    fn close_tab(&mut self, idx: usize) {
+        if self.tabs.len() == 1 {
+            self.status_message = String::from("Cannot close last tab page");
+            return;
+        }
+        if idx >= self.tabs.len() {
+            self.status_message = format!("Tab page {} does not exist", idx + 1);
+            return;
+        }
+        if idx == self.current_tab {
+            self.goto_tab(if idx + 1 < self.tabs.len() {
+                idx + 1
+            } else {
+                idx - 1
+            });
+        }
+        self.tabs.remove(idx);
+        if idx < self.current_tab {
+            self.current_tab -= 1;
+        }
+        self.scroll();
+    }
+
+    /// `:tabonly`: closes every tab page but the current one.
+    fn only_tab(&mut self) {
+        let current = self.tabs.remove(self.current_tab);
+        self.tabs = vec![current];
+        self.current_tab = 0;
+        self.scroll();
+    }
+
+    /// Index of the tab page a `:tabclose` / `:tabnext` count refers to.
+    fn tab_arg(&mut self, arg: &str) -> Option<usize> {
+        if arg.is_empty() {
+            return Some(self.current_tab);
+        }
+        match arg.parse::<usize>() {
+            Ok(n) if n >= 1 => Some(n - 1),
+            _ => {
+                self.status_message = format!("Invalid argument: {}", arg);
+                None
+            }
+        }
+    }
+
+    /// `:split` / `:vsplit`: opens a new window on the current buffer (or on
+    /// `path`) above or to the left of the current one, and moves into it.
+    fn split_window(&mut self, dir: SplitDir, path: &str) {
+        self.save_window();
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        self.layout.split(self.current_window, id, dir);
+        self.windows.push(Window {
+            id,
+            buffer_id: self.buffer.id,
+            cursor: self.cursor,
+            scroll_offset: self.scroll_offset,
+        });
+        self.current_window = id;
+        self.scroll();
+        if !path.is_empty() {
+            self.open_file_or_report(path);
+        }
+    }
+
+    /// Closes window `id`. Its buffer stays listed; the last window cannot be
+    /// closed.
+    fn close_window(&mut self, id: usize) {
+        if !self.has_splits() {
+            self.status_message = String::from("Cannot close last window");
+            return;
+        }
+        if id == self.current_window {
+            let ids = self.layout.window_ids();
+            let pos = ids.iter().position(|&w| w == id).unwrap_or(0);
+            let next = if pos + 1 < ids.len() {
+                ids[pos + 1]
+            } else {
+                ids[pos - 1]
+            };
+            self.switch_window(next);
+        }
+        self.layout.remove(id);
+        self.windows.retain(|w| w.id != id);
+        self.scroll();
+    }
+
+    /// `:only`: closes every window but the current one.
+    fn only_window(&mut self) {
+        let others: Vec<usize> = self
+            .windows
+            .iter()
+            .map(|w| w.id)
+            .filter(|&id| id != self.current_window)
+            .collect();
+        for id in others {
+            self.close_window(id);
+        }
+    }
+
+    /// `:q[!]` closes the current window (or the tab page holding only it),
+    /// quitting with the last one.
+    fn quit_window(&mut self, force: bool) {
+        if self.has_splits() {
+            self.close_window(self.current_window);
+        } else if self.tabs.len() > 1 {
+            self.close_tab(self.current_tab);
+        } else {
+            self.quit_all(force);
+        }
+    }
+
+    /// `:qa[!]`: quits the editor. Without `!` this is refused while any
+    /// buffer has unsaved changes.
+    fn quit_all(&mut self, force: bool) {
+        if !force {
+            if self.buffer.modified() {
+                self.status_message = String::from(NOT_SAVED);
+                return;
+            }
+            if let Some(buffer) = self.buffers.iter().find(|b| b.modified()) {
+                self.status_message = format!(
+                    "E162: No write since last change for buffer \"{}\"",
+                    buffer.name()
+                );
+                return;
+            }
+        }
+        self.should_quit = true;
+    }
+
+    /// Window commands typed after `Ctrl-w`.
+    fn window_command(&mut self, key: char, count: Option<usize>) {
+        let amount = count.unwrap_or(1) as i32;
+        match key {
+            's' | 'S' => self.split_window(SplitDir::Horizontal, ""),
+            'v' => self.split_window(SplitDir::Vertical, ""),
+            'w' | 'W' => {
+                let ids = self.layout.window_ids();
+                let pos = ids.iter().position(|&id| id == self.current_window);
+                let target = match (count, pos) {
+                    (Some(n), _) => ids[(n - 1).min(ids.len() - 1)],
+                    (None, Some(pos)) if key == 'w' => ids[(pos + 1) % ids.len()],
+                    (None, Some(pos)) => ids[(pos + ids.len() - 1) % ids.len()],
+                    (None, None) => return,
+                };
+                self.switch_window(target);
+            }
+            'h' | 'j' | 'k' | 'l' => {
+                for _ in 0..amount {
+                    match self.window_in_direction(key) {
+                        Some(id) => self.switch_window(id),
+                        None => break,
+                    }
+                }
+            }
+            'c' => self.close_window(self.current_window),
+            'q' => self.quit_window(false),
+            'o' => self.only_window(),
+            '=' => self.layout.equalize(),
+            '+' => self.resize_window(SplitDir::Horizontal, amount),
+            '-' => self.resize_window(SplitDir::Horizontal, -amount),
+            '>' => self.resize_window(SplitDir::Vertical, amount),
+            '<' => self.resize_window(SplitDir::Vertical, -amount),
+            '_' => self.resize_command(
+                SplitDir::Horizontal,
+                &count.map_or(String::new(), |n| n.to_string()),
+            ),
+            '|' => self.resize_command(
+                SplitDir::Vertical,
+                &count.map_or(String::new(), |n| n.to_string()),
+            ),
+            _ => {}
+        }
+    }
+
+    /// The window next to the current one on side `key` (`h`, `j`, `k` or
+    /// `l`), preferring the one level with the cursor.
+    fn window_in_direction(&self, key: char) -> Option<usize> {
+        let rects = self.window_rects();
+        let current = self.current_rect();
+        let cursor_x = current.x + self.cursor_vcol().saturating_sub(self.scroll_offset.1) as u16;
+        let cursor_y = current.y + self.cursor.0.saturating_sub(self.scroll_offset.0) as u16;
+        let adjacent = |r: &Rect| match key {
+            'h' => r.x + r.width + 1 == current.x,
+            'l' => r.x == current.x + current.width + 1,
+            'k' => r.y + r.height == current.y,
+            _ => r.y == current.y + current.height,
+        };
+        // Span of a window across the direction of travel.
+        let across = |r: &Rect| match key {
+            'h' | 'l' => r.y..r.y + r.height,
+            _ => r.x..r.x + r.width,
+        };
+        let level = if matches!(key, 'h' | 'l') {
+            cursor_y
+        } else {
+            cursor_x
+        };
+        let candidates: Vec<(usize, Rect)> = rects
+            .into_iter()
+            .filter(|(_, r)| {
+                let (span, own) = (across(r), across(&current));
+                adjacent(r) && span.start < own.end && own.start < span.end
+            })
+            .collect();
+        candidates
+            .iter()
+            .find(|(_, r)| across(r).contains(&level))
+            .or(candidates.first())
+            .map(|&(id, _)| id)
+    }
+
+    fn resize_window(&mut self, dir: SplitDir, delta: i32) {
+        let area = self.screen_area();
+        self.layout.resize(self.current_window, dir, delta, area);
+        self.scroll();
+    }
+
+    /// `:resize [+-]N`: sets the height (or with `:vertical`, the width) of
+    /// the current window, or changes it by N. Without N it is maximized.
+    fn resize_command(&mut self, dir: SplitDir, arg: &str) {
+        let rect = self.current_rect();
+        let current = match dir {
+            SplitDir::Horizontal => rect.height.saturating_sub(self.has_splits() as u16),
+            SplitDir::Vertical => rect.width,
+        } as i32;
+        let delta = match arg.parse::<i32>() {
+            Ok(n) if arg.starts_with(['+', '-']) => n,
+            Ok(n) => n - current,
+            Err(_) if arg.is_empty() => i32::from(u16::MAX),
+            Err(_) => {
+                self.status_message = format!("Invalid argument: {}", arg);
+                return;
+            }
+        };
+        self.resize_window(dir, delta);
+    }
+
+    /// Looks for a swap file of the file just opened. One left by a running
+    /// RUNE, or holding changes that were never saved, is put to the user;
+    /// stale ones are removed.
+    fn check_swap_file(&mut self) {
+        if !self.config.swap_file {
+            return;
+        }
+        let Some(path) = self.buffer.path.clone() else {
+            return;
+        };
+        let Some((swap_path, swap)) = swapfile::find(&self.config.swap_dir(), &path) else {
+            return;
+        };
+        let live = swap.is_live();
+        let unsaved = swap
+            .text
+            .as_ref()
+            .is_some_and(|text| *text != self.buffer.content);
+        if !live && !unsaved {
+            let _ = std::fs::remove_file(&swap_path);
+            return;
+        }
+        let found = if live {
+            format!("Another RUNE (pid {}) is editing \"{}\".", swap.pid, path)
+        } else {
+            format!(
+                "Found a swap file with unsaved changes to \"{}\" (pid {}).",
+                path, swap.pid
+            )
+        };
+        self.status_message = format!("E325: ATTENTION\n{}\n{}", found, SWAP_CHOICES);
+        self.swap_prompt = Some((swap_path, swap));
+    }
+
+    /// Answers the swap file prompt: recover the changes, open the file
+    /// read-only, edit it anyway, delete the swap file or give up on the file.
+    fn handle_swap_key(&mut self, event: KeyEvent) {
+        let Some((swap_path, swap)) = self.swap_prompt.take() else {
+            return;
+        };
+        match event.code {
+            KeyCode::Char('r') => {
+                if let Some(text) = &swap.text {
+                    let len = self.buffer.content.len_chars();
+                    self.buffer.remove(0..len);
+                    self.buffer.insert(0, text);
+                    self.commit_undo();
+                    self.restore_cursor(self.cursor);
+                    self.status_message =
+                        String::from("Recovered unsaved changes; :w to keep them");
+                } else {
+                    self.status_message = String::from("Nothing to recover");
+                }
+                if !swap.is_live() {
+                    let _ = std::fs::remove_file(&swap_path);
+                }
+            }
+            KeyCode::Char('o') => {
+                self.buffer.readonly = true;
+                self.status_message = format!("\"{}\" [RO]", self.buffer.name());
+            }
+            KeyCode::Char('e') => self.status_message.clear(),
+            KeyCode::Char('d') => {
+                let _ = std::fs::remove_file(&swap_path);
+                self.status_message = String::from("Swap file deleted");
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                self.delete_buffer("!");
+                self.status_message.clear();
+            }
+            _ => {
+                self.status_message = format!("E325: ATTENTION\n{}", SWAP_CHOICES);
+                self.swap_prompt = Some((swap_path, swap));
+                return;
+            }
+        }
+        self.write_swap_files();
+        self.scroll();
+    }
+
+    /// Brings swap files up to date: buffers with a file claim one right away,
+    /// and changed buffers are written out every `update_time` ms.
+    pub fn write_swap_files(&mut self) {
+        if !self.config.swap_file {
+            return;
+        }
+        let swap_dir = self.config.swap_dir();
+        let due = self.last_swap_write.elapsed() >= Duration::from_millis(self.config.update_time);
+        let prompting = self.swap_prompt.is_some().then_some(self.buffer.id);
+        let buffers = std::iter::once(&mut self.buffer).chain(self.buffers.iter_mut());
+        for buffer in buffers {
+            let Some(path) = &buffer.path else {
+                continue;
+            };
+            if buffer.readonly || Some(buffer.id) == prompting {
+                continue;
+            }
+            if buffer.swap_path.is_none() {
+                buffer.swap_path = swapfile::free_path(&swap_dir, path);
+            } else if !due || buffer.swap_changes == Some(buffer.changes()) {
+                continue;
+            }
+            let Some(swap_path) = &buffer.swap_path else {
+                continue;
+            };
+            // Copying a large file's text would stall editing, so its swap
+            // file only marks it as being edited.
+            // Nor does it hold the bytes being edited in hex mode.
+            let text = (buffer.modified() && !buffer.large && buffer.hex.is_none())
+                .then_some(&buffer.content);
+            if swapfile::save(swap_path, path, text).is_ok() {
+                buffer.swap_changes = Some(buffer.changes());
+            }
+        }
+        if due {
+            self.last_swap_write = Instant::now();
+        }
+    }
+
+    /// Removes the swap files of all buffers, when quitting.
+    pub fn remove_swap_files(&mut self) {
+        let buffers = std::iter::once(&mut self.buffer).chain(self.buffers.iter_mut());
+        for buffer in buffers {
+            if let Some(swap_path) = buffer.swap_path.take() {
+                let _ = std::fs::remove_file(swap_path);
+            }
+        }
+    }
+
+    fn changed_on_disk(buffer: &Buffer) -> bool {
+        match (&buffer.path, &buffer.disk) {
+            (Some(path), Some(disk)) => {
+                matches!(fileio::check_disk(path, disk), DiskChange::Changed)
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks the files reported by the watcher, and reads what was appended
+    /// to followed files. Returns whether anything may have changed on screen.
+    pub fn poll_file_events(&mut self) -> bool {
+        let followed = self.poll_follow();
+        let Some(watcher) = &mut self.watcher else {
+            return followed;
+        };
+        let changed = watcher.changed_files();
+        if changed.is_empty() {
+            return followed;
+        }
+        let ids: Vec<usize> = std::iter::once(&self.buffer)
+            .chain(&self.buffers)
+            .filter(|b| {
+                b.path
+                    .as_deref()
+                    .and_then(watcher::watch_key)
+                    .is_some_and(|key| changed.contains(&key))
+            })
+            .map(|b| b.id)
+            .collect();
+        self.check_time(&ids);
+        true
+    }
+
+    /// `:checktime`: looks for buffers whose file changed on disk. Unmodified
+    /// buffers are reloaded with `autoread`; otherwise the user is asked.
+    fn check_time(&mut self, ids: &[usize]) {
+        for &id in ids {
+            if self.reload_prompt.is_some() || self.swap_prompt.is_some() {
+                return;
+            }
+            let Some(buffer) = self.buffer_by_id(id) else {
+                continue;
+            };
+            let (Some(path), Some(disk)) = (buffer.path.clone(), buffer.disk) else {
+                continue;
+            };
+            let modified = buffer.modified();
+            match fileio::check_disk(&path, &disk) {
+                DiskChange::Unchanged => {}
+                DiskChange::Deleted => {
+                    self.buffer_mut(id).disk = None;
+                    self.status_message = format!("E211: File \"{}\" no longer available", path);
+                }
+                DiskChange::Changed if !modified && self.config.autoread => {
+                    self.reload_buffer(id);
+                    self.status_message = format!("\"{}\" reloaded", path);
+                }
+                DiskChange::Changed => {
+                    let warning = if modified {
+                        format!(
+                            "W12: Warning: File \"{}\" has changed and the buffer was changed in RUNE as well",
+                            path
+                        )
+                    } else {
+                        format!(
+                            "W11: Warning: File \"{}\" has changed since editing started",
+                            path
+                        )
+                    };
+                    self.status_message = format!("{}\n{}", warning, RELOAD_CHOICES);
+                    self.reload_prompt = Some(id);
+                }
+            }
+        }
+    }
+
+    /// `:follow`: starts or stops following the current buffer's file.
+    fn toggle_follow(&mut self) {
+        if self.buffer.follow.is_some() {
+            self.stop_follow();
+        } else {
+            self.start_follow();
+        }
+    }
+
+    /// `rune -f`: appends what is written to the current buffer's file as it
+    /// arrives, keeping the view at the end. The buffer is read-only meanwhile.
+    pub fn start_follow(&mut self) {
+        let Some(path) = self.buffer.path.clone() else {
+            self.status_message = String::from("E32: No file name");
+            return;
+        };
+        if self.buffer.modified() {
+            self.status_message = String::from(NOT_SAVED);
+            return;
+        }
+        // The buffer holds the file as it was read; if it has shrunk since,
+        // following starts over from the beginning.
+        let offset = self.buffer.disk.map_or(0, |disk| disk.len);
+        let Some(follow) = Follow::new(
+            &path,
+            offset,
+            self.buffer.encoding,
+            self.buffer.fileformat,
+            self.buffer.readonly,
+        ) else {
+            self.status_message = format!("Cannot follow {} files", self.buffer.encoding.name());
+            return;
+        };
+        self.buffer.follow = Some(follow);
+        self.buffer.readonly = true;
+        // Growth is picked up by following rather than reported as a change.
+        self.buffer.disk = None;
+        self.restore_cursor((self.last_line(), 0));
+        self.scroll();
+        self.status_message = format!("Following \"{}\"", path);
+        self.poll_follow();
+    }
+
+    fn stop_follow(&mut self) {
+        // Catch up first, so the buffer matches the disk state recorded below.
+        self.poll_follow();
+        let Some(follow) = self.buffer.follow.take() else {
+            return;
+        };
+        self.buffer.readonly = follow.was_readonly;
+        let path = self.buffer.path.clone().unwrap_or_default();
+        self.buffer.disk = DiskState::read(&path);
+        self.status_message = format!("Stopped following \"{}\"", path);
+    }
+
+    /// Reads what was appended to followed files. Windows showing the last
+    /// line of one move on to the new last line; the others stay where the
+    /// user scrolled to. Returns whether any buffer was updated.
+    fn poll_follow(&mut self) -> bool {
+        let ids: Vec<usize> = std::iter::once(&self.buffer)
+            .chain(&self.buffers)
+            .filter(|b| b.follow.is_some())
+            .map(|b| b.id)
+            .collect();
+        let mut updated = false;
+        for id in ids {
+            let buffer = self.buffer_mut(id);
+            let path = buffer.path.clone().unwrap_or_default();
+            let Some(follow) = &mut buffer.follow else {
+                continue;
+            };
+            let old_last = last_line(&buffer.content);
+            let update = match follow.poll(&path) {
+                Ok(Some(update)) => update,
+                Ok(None) => continue,
+                Err(e) => {
+                    self.status_message = format!("Error following \"{}\": {}", path, e);
+                    updated = true;
+                    continue;
+                }
+            };
+            let reset = matches!(update, FollowUpdate::Reset(_));
+            match update {
+                FollowUpdate::Appended(text) => buffer.append_followed(&text),
+                FollowUpdate::Reset(text) => {
+                    buffer.reset_followed(&text);
+                    self.status_message = format!("\"{}\" was truncated or replaced", path);
+                }
+            }
+            self.pin_to_end(id, old_last, reset);
+            updated = true;
+        }
+        updated
+    }
+
+    /// Moves cursors on buffer `id` that were on its last line, `old_last`,
+    /// to the new last line; all of them when the text was replaced.
+    fn pin_to_end(&mut self, id: usize, old_last: usize, reset: bool) {
+        let new_last = last_line(&self.buffer_mut(id).content);
+        let pin = |cursor: &mut (usize, usize)| {
+            if reset || cursor.0 >= old_last {
+                *cursor = (new_last, 0);
+            }
+        };
+        if self.buffer.id == id {
+            let mut cursor = self.cursor;
+            pin(&mut cursor);
+            if cursor != self.cursor {
+                self.restore_cursor(cursor);
+                self.scroll();
+            }
+        } else if let Some(buffer) = self.buffers.iter_mut().find(|b| b.id == id) {
+            pin(&mut buffer.cursor);
+        }
+        let rects = self.window_rects();
+        let splits = self.has_splits() as usize;
+        for window in self.windows.iter_mut() {
+            if window.buffer_id != id || window.id == self.current_window {
+                continue;
+            }
+            let before = window.cursor;
+            pin(&mut window.cursor);
+            if window.cursor != before
+                && let Some((_, rect)) = rects.iter().find(|(w, _)| *w == window.id)
+            {
+                let height = (rect.height as usize).saturating_sub(splits).max(1);
+                window.scroll_offset.0 = new_last.saturating_sub(height - 1);
+            }
+        }
+        for (i, tab) in self.tabs.iter_mut().enumerate() {
+            if i == self.current_tab {
+                continue;
+            }
+            // Scrolled into view when the tab page is entered.
+            for window in tab.windows.iter_mut().filter(|w| w.buffer_id == id) {
+                pin(&mut window.cursor);
+            }
+        }
+    }
+
+    /// `:hex`: switches the current buffer between its text and its raw bytes.
+    pub fn toggle_hex(&mut self) {
+        if self.buffer.hex.is_some() {
+            self.leave_hex();
+        } else {
+            self.enter_hex();
+        }
+    }
+
+    /// Shows the bytes the buffer would be written as, which for a buffer
+    /// without changes are those of its file.
+    fn enter_hex(&mut self) {
+        self.commit_undo();
+        if let Some(c) = self.buffer.unencodable() {
+            self.status_message = format!(
+                "E513: conversion failed: {:?} is not in {}",
+                c,
+                self.buffer.encoding.name()
+            );
+            return;
+        }
+        let mut bytes = Vec::new();
+        if let Err(e) = self.buffer.write_to(&mut bytes) {
+            self.status_message = format!("Cannot show bytes: {}", e);
+            return;
+        }
+        let len = bytes.len();
+        self.buffer.hex = Some(HexView::new(bytes));
+        self.status_message = format!("{} in hex mode", plural(len, "byte"));
+    }
+
+    /// Goes back to editing text, decoding the bytes again if they were
+    /// changed. That replaces the text as a single undoable change.
+    fn leave_hex(&mut self) {
+        let Some(view) = self.buffer.hex.take() else {
+            return;
+        };
+        if !view.edited {
+            return;
+        }
+        let (text, encoding) = Encoding::decode(&view.bytes);
+        let fileformat = if self.buffer.large {
+            FileFormat::Unix
+        } else {
+            FileFormat::detect(&text)
+        };
+        let text = fileformat.to_buffer(text);
+        self.save_state();
+        let len = self.buffer.content.len_chars();
+        self.buffer.remove(0..len);
+        self.buffer.insert(0, &text);
+        self.buffer.encoding = encoding;
+        self.buffer.fileformat = fileformat;
+        self.commit_undo();
+        if !view.dirty {
+            // The bytes were written, so the new text is what is on disk.
+            self.buffer.mark_saved();
+        }
+        self.restore_cursor(self.cursor);
+        self.scroll();
+    }
+
+    /// Handles a key in hex mode, in Normal or Insert mode. Returns false for
+    /// keys left to Normal mode: commands, searches, windows and tab pages.
+    fn handle_hex_key(&mut self, event: KeyEvent) -> bool {
+        if self.mode == Mode::Insert {
+            self.handle_hex_insert(event);
+        } else {
+            let delegate = matches!(
+                self.pending_prefix,
+                Some(WINDOW_PREFIX | 'Z' | 'q' | '@' | '"')
+            ) || (self.pending_prefix == Some('g')
+                && matches!(event.code, KeyCode::Char('t' | 'T')))
+                || (self.pending_prefix.is_none()
+                    && (matches!(event.code, KeyCode::Char(':' | '/' | '?' | 'Z' | 'q' | '@'))
+                        || (event.code == KeyCode::Char('w')
+                            && event.modifiers.contains(KeyModifiers::CONTROL))));
+            if delegate {
+                return false;
+            }
+            self.handle_hex_normal(event);
         }
+        let area = self.current_rect();
+        let height = area.height.saturating_sub(self.has_splits() as u16) as usize;
+        if let Some(view) = &mut self.buffer.hex {
+            view.scroll_to_cursor(height);
+        }
+        true
     }
 
-    fn handle_normal_mode(&mut self, event: crossterm::event::KeyEvent) {
-        use crossterm::event::KeyCode;
-
-        if let Some(op) = self.pending_operator {
-            match event.code {
-                KeyCode::Char('w')
-                | KeyCode::Char('b')
-                | KeyCode::Char('h')
-                | KeyCode::Char('j')
-                | KeyCode::Char('k')
-                | KeyCode::Char('l')
-                | KeyCode::Char('0')
-                | KeyCode::Char('$') => {
-                    self.execute_operator(op, event.code);
-                    self.pending_operator = None;
+    fn handle_hex_normal(&mut self, event: KeyEvent) {
+        if self.pending_prefix.is_none() && self.push_count_digit(event.code) {
+            return;
+        }
+        let prefix = self.pending_prefix.take();
+        let count = self.take_count();
+        let n = isize::try_from(count.unwrap_or(1)).unwrap_or(isize::MAX);
+        let row = ROW_BYTES as isize;
+        let Some(view) = &mut self.buffer.hex else {
+            return;
+        };
+        match (prefix, event.code) {
+            (Some('g'), KeyCode::Char('g')) => view.move_to(0),
+            (Some(_), _) => {}
+            (None, KeyCode::Char('h') | KeyCode::Left | KeyCode::Backspace) => view.move_by(-n),
+            (None, KeyCode::Char('l' | ' ') | KeyCode::Right) => view.move_by(n),
+            (None, KeyCode::Char('j') | KeyCode::Down) => view.move_by(n.saturating_mul(row)),
+            (None, KeyCode::Char('k') | KeyCode::Up) => view.move_by(n.saturating_mul(-row)),
+            (None, KeyCode::Char('0') | KeyCode::Home) => {
+                view.move_to(view.cursor - view.cursor % ROW_BYTES)
+            }
+            (None, KeyCode::Char('$') | KeyCode::End) => {
+                view.move_to(view.cursor - view.cursor % ROW_BYTES + ROW_BYTES - 1)
+            }
+            (None, KeyCode::Char('G')) => view.move_to(usize::MAX),
+            (None, KeyCode::Char('g')) => self.pending_prefix = Some('g'),
+            (None, KeyCode::Tab) => {
+                view.ascii = !view.ascii;
+                view.low_nibble = false;
+            }
+            (None, KeyCode::Char('u')) => {
+                let undone = (0..n).take_while(|_| view.undo()).count();
+                if undone == 0 {
+                    self.status_message = String::from("Already at oldest change");
                 }
-                KeyCode::Esc => self.pending_operator = None,
-                _ => {}
             }
-            self.scroll();
-            return;
+            (None, KeyCode::Char('n')) => self.hex_search(self.last_search_dir),
+            (None, KeyCode::Char('N')) => self.hex_search(!self.last_search_dir),
+            (None, KeyCode::Char('i' | 'R')) => match self.lock_message() {
+                Some(message) => self.status_message = String::from(message),
+                None => self.mode = Mode::Insert,
+            },
+            (None, KeyCode::Esc) => self.cancel_pending(),
+            _ => {}
         }
+    }
 
+    /// Overwrites bytes: hex digits in the hex column, any text in the ASCII
+    /// column.
+    fn handle_hex_insert(&mut self, event: KeyEvent) {
+        let Some(view) = &mut self.buffer.hex else {
+            return;
+        };
         match event.code {
-            KeyCode::Char('i') => {
-                self.save_state();
-                self.mode = Mode::Insert;
-            }
-            KeyCode::Char('q') => self.should_quit = true,
-            KeyCode::Char('h') => self.move_cursor(0, -1),
-            KeyCode::Char('j') => self.move_cursor(1, 0),
-            KeyCode::Char('k') => self.move_cursor(-1, 0),
-            KeyCode::Char('l') => self.move_cursor(0, 1),
-            KeyCode::Char('w') => self.move_to_next_word(),
-            KeyCode::Char('b') => self.move_to_prev_word(),
-            KeyCode::Char('0') => self.cursor.1 = 0,
-            KeyCode::Char('$') => {
-                let line_idx = self.cursor.0;
-                if line_idx < self.buffer.content.len_lines() {
-                    let len = self.buffer.content.line(line_idx).len_chars();
-                    self.cursor.1 = if len > 0 { len - 1 } else { 0 };
-                }
-            }
-            KeyCode::Char('g') => {
-                // Simplified 'gg'
-                self.cursor = (0, 0);
-            }
-            KeyCode::Char('G') => {
-                let last_line = self.buffer.content.len_lines().saturating_sub(1);
-                self.cursor = (last_line, 0);
-            }
-            KeyCode::Char('d') => self.pending_operator = Some('d'),
-            KeyCode::Char('y') => self.pending_operator = Some('y'),
-            KeyCode::Char('p') => self.paste(),
-            KeyCode::Char(':') => {
-                self.mode = Mode::Command;
-                self.command_buffer = String::from(":");
+            KeyCode::Esc => self.mode = Mode::Normal,
+            KeyCode::Tab => {
+                view.ascii = !view.ascii;
+                view.low_nibble = false;
             }
-            KeyCode::Char('/') => {
-                self.mode = Mode::Command;
-                self.command_buffer = String::from("/");
-            }
-            KeyCode::Char('n') => self.find_next(),
-            KeyCode::Char('N') => self.find_prev(),
-            KeyCode::Char('u') => self.undo(),
-            KeyCode::Char('r')
-                if event
-                    .modifiers
-                    .contains(crossterm::event::KeyModifiers::CONTROL) =>
-            {
-                self.redo()
+            KeyCode::Left | KeyCode::Backspace => view.move_by(-1),
+            KeyCode::Right => view.move_by(1),
+            KeyCode::Down => view.move_by(ROW_BYTES as isize),
+            KeyCode::Up => view.move_by(-(ROW_BYTES as isize)),
+            KeyCode::Char(c) if view.ascii => {
+                for &byte in c.encode_utf8(&mut [0; 4]).as_bytes() {
+                    view.type_byte(byte);
+                }
+            }
+            KeyCode::Char(c) => {
+                if let Some(digit) = c.to_digit(16) {
+                    view.type_nibble(digit as u8);
+                }
             }
             _ => {}
         }
-        self.scroll();
     }
 
-    fn save_state(&mut self) {
-        self.undo_stack.push(self.buffer.content.clone());
-        if self.undo_stack.len() > 50 {
-            self.undo_stack.remove(0);
+    /// Moves to the next match of the last search, read as bytes.
+    fn hex_search(&mut self, forward: bool) {
+        if self.search_query.is_empty() {
+            self.status_message = String::from("No previous regular expression");
+            return;
         }
-        self.redo_stack.clear();
+        let pattern = hex::parse_pattern(&self.search_query);
+        let Some(view) = &mut self.buffer.hex else {
+            return;
+        };
+        let Some((offset, wrapped)) = view.find(&pattern, forward) else {
+            self.status_message = format!("Pattern not found: {}", self.search_query);
+            return;
+        };
+        view.move_to(offset);
+        self.status_message = match (wrapped, forward) {
+            (true, true) => String::from("search hit BOTTOM, continuing at TOP"),
+            (true, false) => String::from("search hit TOP, continuing at BOTTOM"),
+            (false, true) => format!("/{}", self.search_query),
+            (false, false) => format!("?{}", self.search_query),
+        };
     }
 
-    fn undo(&mut self) {
-        if let Some(state) = self.undo_stack.pop() {
-            self.redo_stack.push(self.buffer.content.clone());
-            self.buffer.content = state;
+    /// Answers the changed-file prompt: `o` keeps the buffer as it is, `l`
+    /// loads the file.
+    fn handle_reload_key(&mut self, event: KeyEvent) {
+        let Some(id) = self.reload_prompt.take() else {
+            return;
+        };
+        match event.code {
+            KeyCode::Char('o') | KeyCode::Esc => {
+                // Take the new file as the one being edited, so it is not
+                // reported again.
+                let buffer = self.buffer_mut(id);
+                buffer.disk = buffer.path.as_deref().and_then(DiskState::read);
+                self.status_message.clear();
+            }
+            KeyCode::Char('l') => {
+                self.reload_buffer(id);
+                self.status_message.clear();
+            }
+            _ => {
+                self.status_message = String::from(RELOAD_CHOICES);
+                self.reload_prompt = Some(id);
+            }
         }
     }
 
-    fn redo(&mut self) {
-        if let Some(state) = self.redo_stack.pop() {
-            self.undo_stack.push(self.buffer.content.clone());
-            self.buffer.content = state;
+    fn buffer_mut(&mut self, id: usize) -> &mut Buffer {
+        if self.buffer.id == id {
+            return &mut self.buffer;
         }
+        self.buffers
+            .iter_mut()
+            .find(|b| b.id == id)
+            .unwrap_or(&mut self.buffer)
     }
 
-    fn handle_command_mode(&mut self, event: crossterm::event::KeyEvent) {
-        use crossterm::event::KeyCode;
-        match event.code {
-            KeyCode::Esc => {
-                self.mode = Mode::Normal;
-                self.command_buffer.clear();
-            }
-            KeyCode::Enter => {
-                let cmd = self.command_buffer.clone();
-                self.execute_command(cmd);
-                self.mode = Mode::Normal;
-                self.command_buffer.clear();
-            }
-            KeyCode::Char(c) => self.command_buffer.push(c),
-            KeyCode::Backspace => {
-                if self.command_buffer.len() > 1 {
-                    self.command_buffer.pop();
-                } else {
-                    self.mode = Mode::Normal;
-                    self.command_buffer.clear();
-                }
-            }
-            _ => {}
+    fn reload_buffer(&mut self, id: usize) {
+        if id == self.buffer.id {
+            self.commit_undo();
+            self.revert_buffer();
+            self.scroll();
+        } else if let Some(buffer) = self.buffers.iter_mut().find(|b| b.id == id) {
+            Self::read_back(
+                buffer,
+                self.config.large_file_threshold,
+                self.progress.as_deref(),
+            );
         }
     }
 
-    fn execute_command(&mut self, cmd: String) {
-        if cmd.starts_with(':') {
-            let parts: Vec<&str> = cmd[1..].split_whitespace().collect();
-            if parts.is_empty() {
+    /// Writes the current buffer to `path` or its own file; a read-only
+    /// buffer needs `force`. Returns whether it was written.
+    fn save_file(&mut self, path: Option<String>, force: bool) -> bool {
+        let Some(path) = path.or_else(|| self.buffer.path.clone()) else {
+            self.status_message = String::from("No file path specified");
+            return false;
+        };
+        if self.buffer.readonly && !force {
+            self.status_message = String::from(READONLY);
+            return false;
+        }
+        if !force
+            && self.buffer.path.as_deref() == Some(path.as_str())
+            && Self::changed_on_disk(&self.buffer)
+        {
+            self.status_message = String::from(CHANGED_ON_DISK);
+            return false;
+        }
+        self.commit_undo();
+        let result = Self::write_buffer(&self.config, &mut self.buffer, &path);
+        let saved = result.is_ok();
+        if saved && let Some(watcher) = &mut self.watcher {
+            watcher.watch(&path);
+        }
+        match result {
+            Ok(message) | Err(message) => self.status_message = message,
+        }
+        saved
+    }
+
+    /// `:wa`: writes every modified buffer that has a file.
+    fn write_all(&mut self) {
+        self.commit_undo();
+        let mut written = 0;
+        let buffers = std::iter::once(&mut self.buffer).chain(self.buffers.iter_mut());
+        for buffer in buffers.filter(|b| b.modified()) {
+            let Some(path) = buffer.path.clone() else {
+                self.status_message = format!("E141: No file name for buffer {}", buffer.id);
+                return;
+            };
+            if buffer.readonly {
+                self.status_message = format!("{} for \"{}\"", READONLY, path);
                 return;
             }
-
-            match parts[0] {
-                "q" => self.should_quit = true,
-                "w" => {
-                    let path = cmd[1..].trim_start_matches('w').trim();
-                    if !path.is_empty() {
-                        self.save_file(Some(path.to_string()));
-                    } else {
-                        self.save_file(None);
-                    }
-                }
-                "wq" => {
-                    self.save_file(None);
-                    self.should_quit = true;
-                }
-                "e" => {
-                    let path = cmd[1..].trim_start_matches('e').trim();
-                    if !path.is_empty() {
-                        self.open_file(path);
-                    }
-                }
-                _ => {}
+            if Self::changed_on_disk(buffer) {
+                self.status_message = format!("\"{}\": {}", path, CHANGED_ON_DISK);
+                return;
             }
-        } else if cmd.starts_with('/') {
-            self.search_query = cmd[1..].to_string();
-            self.last_search_dir = true;
-            self.find_next();
+            if let Err(e) = Self::write_buffer(&self.config, buffer, &path) {
+                self.status_message = e;
+                return;
+            }
+            written += 1;
         }
+        self.status_message = format!("{} written", plural(written, "file"));
     }
 
-    pub fn open_file(&mut self, path: &str) {
-        if let Ok(content) = std::fs::read_to_string(path) {
-            self.buffer = Buffer::from_str(&content);
-            self.file_path = Some(path.to_string());
-            self.cursor = (0, 0);
-            self.scroll_offset = (0, 0);
-            self.undo_stack.clear();
-            self.redo_stack.clear();
+    /// Writes `buffer` to `path` and binds it to that file, along with its
+    /// undo history when `undo_file` is on. Returns the message to show.
+    fn write_buffer(config: &Config, buffer: &mut Buffer, path: &str) -> Result<String, String> {
+        if let Some(c) = buffer.unencodable() {
+            return Err(format!(
+                "E513: write error, conversion failed: {:?} is not in {}",
+                c,
+                buffer.encoding.name()
+            ));
         }
+        let disk = fileio::write_file(path, &|out| buffer.write_to(out), config)
+            .map_err(|e| format!("Error saving: {}", e))?;
+        buffer.path = Some(path.to_string());
+        buffer.disk = Some(disk);
+        buffer.mark_saved();
+        // The swap file no longer holds anything to recover.
+        buffer.swap_changes = None;
+        if config.undo_file
+            && !buffer.large
+            && let Err(e) =
+                undofile::save(&config.undo_dir(), path, &buffer.content, &buffer.history)
+        {
+            return Ok(format!("Saved to {} (undo history not saved: {})", path, e));
+        }
+        Ok(format!("Saved to {}", path))
     }
 
-    fn save_file(&mut self, path: Option<String>) {
-        let save_path = path.or_else(|| self.file_path.clone());
-        if let Some(p) = save_path {
-            let content = self.buffer.content.to_string();
-            match std::fs::write(&p, content) {
-                Ok(_) => {
-                    self.file_path = Some(p.clone());
-                    self.status_message = format!("Saved to {}", p);
+    /// `:set {option}`, `:set no{option}` and `:set {option}!` for the boolean
+    /// options, `:set tabstop=N`, and `:set ff=` / `:set fenc=` (shown by
+    /// `:set ff` / `:set fenc`) for the current buffer.
+    fn set_option(&mut self, arg: &str) {
+        if let Some((name, value)) = arg.split_once('=') {
+            let valid = match name {
+                "tabstop" | "ts" => value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .map(|n| self.config.tab_size = n),
+                "fileformat" | "ff" => {
+                    FileFormat::parse(value).map(|ff| self.buffer.fileformat = ff)
+                }
+                "fileencoding" | "fenc" => {
+                    Encoding::parse(value).map(|enc| self.buffer.encoding = enc)
                 }
-                Err(e) => self.status_message = format!("Error saving: {}", e),
+                _ => None,
+            };
+            if valid.is_none() {
+                self.status_message = format!("Invalid argument: {}", arg);
             }
-        } else {
-            self.status_message = String::from("No file path specified");
+            return;
+        }
+        match arg {
+            "fileformat" | "ff" => {
+                self.status_message = format!("fileformat={}", self.buffer.fileformat.name());
+                return;
+            }
+            "fileencoding" | "fenc" => {
+                self.status_message = format!("fileencoding={}", self.buffer.encoding.name());
+                return;
+            }
+            _ => {}
         }
+        let (name, value) = if let Some(name) = arg.strip_suffix('!') {
+            (name, None)
+        } else if let Some(name) = arg.strip_prefix("no") {
+            (name, Some(false))
+        } else {
+            (arg, Some(true))
+        };
+        let option = match name {
+            "ignorecase" | "ic" => &mut self.config.ignorecase,
+            "smartcase" | "scs" => &mut self.config.smartcase,
+            "hlsearch" | "hls" => &mut self.config.hlsearch,
+            "incsearch" | "is" => &mut self.config.incsearch,
+            "autoread" | "ar" => &mut self.config.autoread,
+            "readonly" | "ro" => &mut self.buffer.readonly,
+            "modifiable" | "ma" => &mut self.buffer.modifiable,
+            _ => {
+                self.status_message = format!("Unknown option: {}", arg);
+                return;
+            }
+        };
+        *option = value.unwrap_or(!*option);
+        // Case options change how the last pattern matches.
+        self.compile_search();
+        self.search_highlight = true;
     }
 
-    fn find_next(&mut self) {
+    fn compile_search(&mut self) {
+        self.search_regex = None;
         if self.search_query.is_empty() {
             return;
         }
-        let char_idx = self.cursor_to_char_idx() + 1;
-        let content = self.buffer.content.to_string();
-        if let Some(pos) = content[char_idx..].find(&self.search_query) {
-            self.char_idx_to_cursor(char_idx + pos);
-        } else if let Some(pos) = content[..char_idx].find(&self.search_query) {
-            // Wrap around
-            self.char_idx_to_cursor(pos);
+        match search::compile(
+            &self.search_query,
+            self.config.ignorecase,
+            self.config.smartcase,
+        ) {
+            Ok(regex) => self.search_regex = Some(regex),
+            Err(e) => self.status_message = format!("Invalid pattern: {}", e),
         }
     }
 
-    fn find_prev(&mut self) {
+    /// Jumps to the `count`th match of the last search pattern, applying the
+    /// last search offset.
+    fn search(&mut self, forward: bool, count: usize) {
         if self.search_query.is_empty() {
+            self.status_message = String::from("No previous regular expression");
             return;
         }
-        let char_idx = self.cursor_to_char_idx();
-        let content = self.buffer.content.to_string();
-        if let Some(pos) = content[..char_idx].rfind(&self.search_query) {
-            self.char_idx_to_cursor(pos);
-        } else if let Some(pos) = content[char_idx..].rfind(&self.search_query) {
-            // Wrap around
-            self.char_idx_to_cursor(char_idx + pos);
+        let Some(regex) = self.search_regex.clone() else {
+            return;
+        };
+        let mut from = self.cursor_to_char_idx();
+        if let Some((cursor_idx, match_start)) = self.last_match
+            && cursor_idx == from
+        {
+            from = match_start;
+        }
+
+        let mut found = None;
+        let mut wrapped = false;
+        for _ in 0..count {
+            let found_match = if self.buffer.large {
+                search::find_in_blocks(&self.buffer.content, &regex, from, forward)
+            } else {
+                search::find(&self.buffer.content, &regex, from, forward)
+            };
+            let Some((m, wrap)) = found_match else {
+                break;
+            };
+            from = m.start;
+            wrapped |= wrap;
+            found = Some(m);
         }
+        let Some(m) = found else {
+            self.status_message = format!("Pattern not found: {}", self.search_query);
+            return;
+        };
+
+        let target = search::apply_offset(&self.buffer.content, &m, self.search_offset);
+        self.char_idx_to_cursor(target);
+        self.last_match = Some((target, m.start));
+        self.search_highlight = true;
+        self.status_message = match (wrapped, forward) {
+            (true, true) => String::from("search hit BOTTOM, continuing at TOP"),
+            (true, false) => String::from("search hit TOP, continuing at BOTTOM"),
+            (false, true) => format!("/{}", self.search_query),
+            (false, false) => format!("?{}", self.search_query),
+        };
     }
 
-    fn execute_operator(&mut self, op: char, motion_code: KeyCode) {
-        self.save_state();
+    /// `*` / `#`: searches for the whole keyword under or after the cursor.
+    fn search_word_under_cursor(&mut self, forward: bool, count: usize) {
+        let is_keyword = |c: char| c.is_alphanumeric() || c == '_';
+        let row = self.cursor.0;
+        let line: Vec<char> = self.buffer.content.line(row).chars().collect();
+        let Some(start) = (self.cursor.1..line.len()).find(|&i| is_keyword(line[i])) else {
+            self.status_message = String::from("No string under cursor");
+            return;
+        };
+        let start = (0..start)
+            .rev()
+            .take_while(|&i| is_keyword(line[i]))
+            .last()
+            .unwrap_or(start);
+        let end = (start..line.len())
+            .find(|&i| !is_keyword(line[i]))
+            .unwrap_or(line.len());
+        let word: String = line[start..end].iter().collect();
+
+        self.search_query = format!(r"\b{}\b", regex::escape(&word));
+        self.search_offset = SearchOffset::None;
+        self.last_search_dir = forward;
+        self.last_match = None;
+        self.registers.last_search = self.search_query.clone();
+        self.compile_search();
+        self.cursor.1 = start;
+        self.search(forward, count);
+    }
+
+    /// Column ranges of search matches to highlight on line `row`: the pattern
+    /// being typed during incsearch, else the last search when `hlsearch` is on.
+    pub fn search_highlights(&self, buffer: &Buffer, row: usize) -> Vec<Range<usize>> {
+        let regex = if self.mode == Mode::Command && self.incsearch_regex.is_some() {
+            self.incsearch_regex.as_ref()
+        } else if self.config.hlsearch && self.search_highlight {
+            self.search_regex.as_ref()
+        } else {
+            None
+        };
+        regex.map_or_else(Vec::new, |regex| {
+            search::line_matches(&buffer.content, regex, row)
+                .into_iter()
+                .filter(|m| !m.is_empty())
+                .collect()
+        })
+    }
+
+    fn execute_operator(&mut self, op: char, motion: Motion, count: Option<usize>) {
         let start_idx = self.cursor_to_char_idx();
+        self.apply_motion_count(motion, count);
+        let end_idx = self.cursor_to_char_idx();
 
-        // Save current cursor
-        let old_cursor = self.cursor;
-
-        // Apply motion
-        match motion_code {
-            KeyCode::Char('w') => self.move_to_next_word(),
-            KeyCode::Char('b') => self.move_to_prev_word(),
-            KeyCode::Char('h') => self.move_cursor(0, -1),
-            KeyCode::Char('j') => self.move_cursor(1, 0),
-            KeyCode::Char('k') => self.move_cursor(-1, 0),
-            KeyCode::Char('l') => self.move_cursor(0, 1),
-            _ => {}
+        if motion.is_linewise() {
+            let start_row = self.buffer.content.char_to_line(start_idx);
+            let (first, last) = (start_row.min(self.cursor.0), start_row.max(self.cursor.0));
+            let range = self.line_range(first, last);
+            self.apply_range_operator(op, range, true);
+            return;
         }
 
-        let end_idx = self.cursor_to_char_idx();
-        let range = if start_idx < end_idx {
+        let mut range = if start_idx < end_idx {
             start_idx..end_idx
         } else {
             end_idx..start_idx
         };
-
-        if op == 'd' {
-            let deleted = self.buffer.content.slice(range.clone()).to_string();
-            self.clipboard = deleted;
-            self.buffer.content.remove(range);
-            // After delete, cursor should stay at start of range
-            self.char_idx_to_cursor(if start_idx < end_idx {
-                start_idx
-            } else {
-                end_idx
-            });
-        } else if op == 'y' {
-            let yanked = self.buffer.content.slice(range).to_string();
-            self.clipboard = yanked;
-            self.cursor = old_cursor; // Reset cursor after yank
+        // As in Vim, `cw` on a word changes only up to the end of the word.
+        if op == 'c'
+            && motion == Motion::WordForward
+            && start_idx < self.buffer.content.len_chars()
+            && !self.buffer.content.char(start_idx).is_whitespace()
+        {
+            while range.end > range.start + 1
+                && self.buffer.content.char(range.end - 1).is_whitespace()
+            {
+                range.end -= 1;
+            }
         }
+        self.apply_range_operator(op, range, false);
     }
 
-    fn paste(&mut self) {
-        if self.clipboard.is_empty() {
+    /// Pastes the selected register `count` times: linewise text below (or
+    /// above) the current line, charwise text after (or before) the cursor and
+    /// blockwise text column-wise across lines.
+    fn paste(&mut self, count: usize, before: bool) {
+        let name = self.pending_register.take().unwrap_or('"');
+        let Some(reg) = self.read_register(name) else {
+            return;
+        };
+        if reg.text.is_empty() {
             return;
         }
-        let idx = self.cursor_to_char_idx();
-        self.buffer.content.insert(idx, &self.clipboard);
+        if !repeat_fits(reg.text.len(), count) {
+            self.status_message = String::from(TOO_LONG);
+            return;
+        }
+        self.save_state();
+
+        match reg.kind {
+            RegisterKind::Linewise => {
+                let row = if before {
+                    self.cursor.0
+                } else {
+                    self.cursor.0 + 1
+                };
+                let text = reg.text.repeat(count);
+                if row < self.buffer.content.len_lines() {
+                    let idx = self.buffer.content.line_to_char(row);
+                    self.buffer.insert(idx, &text);
+                } else {
+                    // Below a last line that has no trailing line break.
+                    let len = self.buffer.content.len_chars();
+                    let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                    self.buffer.insert(len, &text);
+                }
+                self.cursor = (row, self.first_non_blank(row));
+            }
+            RegisterKind::Charwise => {
+                let text = reg.text.repeat(count);
+                let mut idx = self.cursor_to_char_idx();
+                if !before && self.line_len(self.cursor.0) > 0 {
+                    // After the whole grapheme cluster under the cursor.
+                    let line_start = self.buffer.content.line_to_char(self.cursor.0);
+                    idx = (line_start + self.grapheme_end(self.cursor))
+                        .min(self.buffer.content.len_chars());
+                }
+                self.buffer.insert(idx, &text);
+                self.char_idx_to_cursor(idx + text.chars().count().saturating_sub(1));
+            }
+            RegisterKind::Blockwise => {
+                let col = if !before && self.line_len(self.cursor.0) > 0 {
+                    self.grapheme_end(self.cursor)
+                } else {
+                    self.cursor.1
+                };
+                let pieces: Vec<&str> = reg.text.split('\n').collect();
+                let width = pieces.iter().map(|p| p.chars().count()).max().unwrap_or(0);
+                for (i, piece) in pieces.iter().enumerate() {
+                    let row = self.cursor.0 + i;
+                    while row >= self.buffer.content.len_lines() {
+                        let len = self.buffer.content.len_chars();
+                        self.buffer.insert_char(len, '\n');
+                    }
+                    let len = self.line_len(row);
+                    let line_start = self.buffer.content.line_to_char(row);
+                    if len < col {
+                        self.buffer.insert(line_start + len, &" ".repeat(col - len));
+                    }
+                    let mut cell = piece.to_string();
+                    if col < len {
+                        // Keep the columns after the block aligned.
+                        cell.push_str(&" ".repeat(width - piece.chars().count()));
+                    }
+                    self.buffer.insert(line_start + col, &cell.repeat(count));
+                }
+                self.cursor.1 = col;
+            }
+        }
     }
 
+    /// Moves the cursor by lines, keeping to the screen column in
+    /// `curswant`, and by grapheme clusters along the line.
     fn move_cursor(&mut self, row_delta: i32, col_delta: i32) {
-        let mut row = self.cursor.0 as i32 + row_delta;
-        let mut col = self.cursor.1 as i32 + col_delta;
+        let tab_size = self.config.tab_size;
+        if row_delta != 0 {
+            let want = match self.curswant {
+                Some((want, at)) if at == self.cursor => want,
+                _ => self.cursor_vcol(),
+            };
+            let last_row = self.buffer.content.len_lines().saturating_sub(1);
+            let row = self
+                .cursor
+                .0
+                .saturating_add_signed(row_delta as isize)
+                .min(last_row);
+            let col = display::col_at(&self.buffer.line_text(row), want, tab_size);
+            self.cursor = (row, col.min(self.max_col(row)));
+            self.curswant = Some((want, self.cursor));
+            if col_delta == 0 {
+                return;
+            }
+        }
+        let line = self.buffer.line_text(self.cursor.0);
+        let col = display::step(&line, self.cursor.1, col_delta as isize);
+        self.cursor.1 = col.min(self.max_col(self.cursor.0));
+    }
 
-        let line_count = self.buffer.content.len_lines();
-        row = row.clamp(0, line_count.saturating_sub(1) as i32);
+    /// The last column the Normal-mode cursor reaches on line `row`: its line
+    /// break, or the last grapheme cluster of a final line without one.
+    fn max_col(&self, row: usize) -> usize {
+        let len = self.buffer.content.line(row).len_chars();
+        display::step(&self.buffer.line_text(row), len.saturating_sub(1), 0)
+    }
 
-        let line_len = self.buffer.content.line(row as usize).len_chars();
-        col = col.clamp(0, line_len.saturating_sub(1).max(0) as i32);
+    /// Char column just after the grapheme cluster at `pos`.
+    fn grapheme_end(&self, (row, col): (usize, usize)) -> usize {
+        display::step(&self.buffer.line_text(row), col, 1)
+    }
 
-        self.cursor = (row as usize, col as usize);
+    /// Screen column of the cursor in its line.
+    pub fn cursor_vcol(&self) -> usize {
+        let line = self.buffer.line_text(self.cursor.0);
+        display::vcol(&line, self.cursor.1, self.config.tab_size)
     }
 
     fn move_to_next_word(&mut self) {
@@ -365,7 +3581,24 @@ impl Editor {
     }
 
     fn cursor_to_char_idx(&self) -> usize {
-        self.buffer.content.line_to_char(self.cursor.0) + self.cursor.1
+        self.pos_to_char_idx(self.cursor)
+    }
+
+    fn pos_to_char_idx(&self, pos: (usize, usize)) -> usize {
+        self.buffer.content.line_to_char(pos.0) + pos.1
+    }
+
+    /// Length of line `row` in chars, excluding its line break.
+    pub fn line_len(&self, row: usize) -> usize {
+        if row >= self.buffer.content.len_lines() {
+            return 0;
+        }
+        let line = self.buffer.content.line(row);
+        let mut len = line.len_chars();
+        while len > 0 && matches!(line.char(len - 1), '\n' | '\r') {
+            len -= 1;
+        }
+        len
     }
 
     fn char_idx_to_cursor(&mut self, char_idx: usize) {
@@ -375,8 +3608,9 @@ impl Editor {
     }
 
     fn scroll(&mut self) {
-        let (width, height) = self.terminal_size;
-        let height = height.saturating_sub(1) as usize; // Reserve space for status bar
+        let area = self.current_rect();
+        let width = area.width;
+        let height = area.height.saturating_sub(self.has_splits() as u16) as usize;
 
         if self.cursor.0 < self.scroll_offset.0 {
             self.scroll_offset.0 = self.cursor.0;
@@ -384,18 +3618,37 @@ impl Editor {
             self.scroll_offset.0 = self.cursor.0 - height + 1;
         }
 
-        if self.cursor.1 < self.scroll_offset.1 {
-            self.scroll_offset.1 = self.cursor.1;
-        } else if self.cursor.1 >= self.scroll_offset.1 + width as usize {
-            self.scroll_offset.1 = self.cursor.1 - width as usize + 1;
+        // Horizontally in screen columns, so all of a wide char shows.
+        let line = self.buffer.line_text(self.cursor.0);
+        let tab_size = self.config.tab_size;
+        let first = display::vcol(&line, self.cursor.1, tab_size);
+        let end = display::vcol(&line, display::step(&line, self.cursor.1, 1), tab_size);
+        if first < self.scroll_offset.1 {
+            self.scroll_offset.1 = first;
+        } else if end > self.scroll_offset.1 + width as usize {
+            self.scroll_offset.1 = end.saturating_sub(width as usize);
         }
     }
 
     fn handle_insert_mode(&mut self, event: crossterm::event::KeyEvent) {
         use crossterm::event::KeyCode;
         match event.code {
-            KeyCode::Esc => self.mode = Mode::Normal,
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                if let Some(change) = &self.pending_change
+                    && change.target == ChangeTarget::Insert
+                {
+                    self.repeat_insert(change.count);
+                }
+                self.registers.last_inserted = self.insert_text.clone();
+                if let Some(mut change) = self.pending_change.take() {
+                    change.inserted = self.insert_text.clone();
+                    self.last_change = Some(change);
+                }
+                self.finish_block_insert();
+            }
             KeyCode::Char(c) => {
+                self.insert_text.push(c);
                 let idx = self.cursor_to_char_idx();
                 self.buffer.insert_char(idx, c);
                 if c == '\n' {
@@ -406,17 +3659,29 @@ impl Editor {
                 }
             }
             KeyCode::Enter => {
+                self.insert_text.push('\n');
                 let idx = self.cursor_to_char_idx();
                 self.buffer.insert_char(idx, '\n');
                 self.cursor.0 += 1;
                 self.cursor.1 = 0;
+                // Each typed line is its own undo step.
+                self.save_state();
             }
             KeyCode::Backspace => {
                 let idx = self.cursor_to_char_idx();
                 if idx > 0 {
+                    // A whole grapheme cluster goes at once.
+                    let line = self.buffer.line_text(self.cursor.0);
+                    let len = match self.cursor.1 {
+                        0 => 1,
+                        col => col - display::step(&line, col, -1),
+                    };
+                    for _ in 0..len {
+                        self.insert_text.pop();
+                    }
                     // Update cursor before delete if at start of line
-                    let pos_before = self.char_idx_to_pos(idx - 1);
-                    self.buffer.delete_char(idx - 1);
+                    let pos_before = self.char_idx_to_pos(idx - len);
+                    self.buffer.remove(idx - len..idx);
                     self.cursor = pos_before;
                 }
             }
@@ -430,4 +3695,310 @@ impl Editor {
         let col = char_idx - self.buffer.content.line_to_char(row);
         (row, col)
     }
+
+    /// Types the text of the Insert session `count - 1` more times, for `3i`.
+    fn repeat_insert(&mut self, count: Option<usize>) {
+        let times = count.unwrap_or(1).saturating_sub(1);
+        if times == 0 || self.insert_text.is_empty() {
+            return;
+        }
+        if !repeat_fits(self.insert_text.len(), times) {
+            self.status_message = String::from(TOO_LONG);
+            return;
+        }
+        let text = self.insert_text.repeat(times);
+        let idx = self.cursor_to_char_idx();
+        self.buffer.insert(idx, &text);
+        self.cursor = self.char_idx_to_pos(idx + text.chars().count());
+    }
+
+    /// Replicates the text typed after a blockwise `c` onto the other lines of
+    /// the block.
+    fn finish_block_insert(&mut self) {
+        let Some(block) = self.block_insert.take() else {
+            return;
+        };
+        if self.cursor.0 != block.row || self.cursor.1 <= block.col {
+            return;
+        }
+        let line_start = self.buffer.content.line_to_char(block.row);
+        let typed = self
+            .buffer
+            .content
+            .slice(line_start + block.col..line_start + self.cursor.1)
+            .to_string();
+        for row in block.rows {
+            if self.line_len(row) >= block.col {
+                let idx = self.buffer.content.line_to_char(row) + block.col;
+                self.buffer.insert(idx, &typed);
+            }
+        }
+    }
+}
+
+/// Whether `count` copies of `len` bytes stay within `MAX_REPEAT_BYTES`.
+fn repeat_fits(len: usize, count: usize) -> bool {
+    len.checked_mul(count)
+        .is_some_and(|total| total <= MAX_REPEAT_BYTES)
+}
+
+/// Argument of `:earlier` / `:later`: a number of changes or a time span.
+enum UndoOffset {
+    Steps(usize),
+    Time(Duration),
+}
+
+/// Parses `N`, `Ns`, `Nm`, `Nh` or `Nd`.
+fn parse_undo_offset(arg: &str) -> Option<UndoOffset> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let n: u64 = arg[..split].parse().ok()?;
+    let unit = match &arg[split..] {
+        "" => return Some(UndoOffset::Steps(n as usize)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 60 * 60 * 24,
+        _ => return None,
+    };
+    let secs = n.checked_mul(unit)?;
+    Some(UndoOffset::Time(Duration::from_secs(secs)))
+}
+
+/// Direction of a `/` or `?` search command line, if it is one.
+fn search_direction(cmd: &str) -> Option<bool> {
+    match cmd.chars().next() {
+        Some('/') => Some(true),
+        Some('?') => Some(false),
+        _ => None,
+    }
+}
+
+/// Loads `path`, reporting how much of a large file has been read through
+/// `progress` as the percentage grows.
+fn load_buffer(
+    path: &str,
+    threshold: u64,
+    progress: Option<&Progress>,
+) -> Result<Buffer, OpenError> {
+    let mut shown = None;
+    Buffer::load(path, threshold, &mut |read, total| {
+        let percent = read * 100 / total.max(1);
+        if let Some(progress) = progress
+            && shown != Some(percent)
+        {
+            shown = Some(percent);
+            progress(&format!("\"{}\" {}%", path, percent));
+        }
+    })
+}
+
+/// Index of the last line of `text`, not counting the empty line ropey reports
+/// after a trailing line break.
+fn last_line(text: &Rope) -> usize {
+    let lines = text.len_lines();
+    if lines > 1 && text.line(lines - 1).len_chars() == 0 {
+        lines - 2
+    } else {
+        lines.saturating_sub(1)
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            if c.is_uppercase() {
+                c.to_lowercase().collect::<Vec<_>>()
+            } else {
+                c.to_uppercase().collect::<Vec<_>>()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn editor(text: &str) -> Editor {
+        let mut editor = Editor::new(Config::default());
+        editor.buffer = Buffer::from_str(text);
+        editor.terminal_size = (80, 24);
+        editor
+    }
+
+    /// Feeds keys written in Vim's key notation (`<Esc>`, `<C-v>`).
+    fn type_keys(editor: &mut Editor, text: &str) {
+        for event in keys::decode(text) {
+            editor.handle_key_event(event);
+        }
+    }
+
+    fn text(editor: &Editor) -> String {
+        editor.buffer.content.to_string()
+    }
+
+    #[test]
+    fn edits_at_the_end_of_a_final_line_without_a_break() {
+        for (start, keys, expected, cursor) in [
+            ("ab cd", "wdw", "ab ", (0, 2)),
+            ("ab cd", "wdwp", "ab cd", (0, 4)),
+            ("ab", "ylwp", "aba", (0, 2)),
+            ("ab cd", "wv9wd", "ab ", (0, 2)),
+            ("ab cd", "wv9wdp", "ab cd", (0, 4)),
+            ("ab cd", "9w", "ab cd", (0, 4)),
+        ] {
+            let mut editor = editor(start);
+            type_keys(&mut editor, keys);
+            assert_eq!(text(&editor), expected, "{}", keys);
+            assert_eq!(editor.cursor, cursor, "{}", keys);
+        }
+    }
+
+    #[test]
+    fn visual_mode() {
+        for (start, keys, expected, cursor) in [
+            ("one two\n", "wvlld", "one \n", (0, 4)),
+            ("one\ntwo\nthree\n", "jVd", "one\nthree\n", (1, 0)),
+            ("abc\ndef\nghi\n", "l<C-v>jld", "a\nd\nghi\n", (0, 1)),
+            ("abc\n", "vlyp", "aabbc\n", (0, 2)),
+            ("ab\ncd\n", "<C-v>jyp", "aab\nccd\n", (0, 1)),
+            ("abcde\n", "lvllohd", "e\n", (0, 0)),
+            ("ab\ncd\n", "<C-v>jcX<Esc>", "Xb\nXd\n", (0, 1)),
+            ("a\nb\n", "Vj>", "    a\n    b\n", (0, 4)),
+            ("    a\n    b\n", "Vj<", "a\nb\n", (0, 0)),
+            ("abc\n", "vl~", "ABc\n", (0, 0)),
+            ("ab\ncd", "jlvd", "ab\nc", (1, 0)),
+            ("ab\ncd\n", "jVd", "ab\n", (0, 0)),
+        ] {
+            let mut editor = editor(start);
+            type_keys(&mut editor, keys);
+            assert_eq!(text(&editor), expected, "{}", keys);
+            assert_eq!(editor.cursor, cursor, "{}", keys);
+            assert_eq!(editor.mode, Mode::Normal, "{}", keys);
+        }
+    }
+
+    #[test]
+    fn huge_paste_counts_are_refused() {
+        let mut editor = editor("ab\n");
+        type_keys(&mut editor, "yl99999999999999999999p");
+        assert_eq!(editor.status_message, TOO_LONG);
+        type_keys(&mut editor, "9999999999p");
+        assert_eq!(editor.status_message, TOO_LONG);
+        assert_eq!(text(&editor), "ab\n");
+        type_keys(&mut editor, "3p");
+        assert_eq!(text(&editor), "aaaab\n");
+    }
+
+    #[test]
+    fn huge_insert_counts_insert_once() {
+        let mut editor = editor("\n");
+        type_keys(&mut editor, "99999999999999999999ix<Esc>");
+        assert_eq!(editor.status_message, TOO_LONG);
+        assert_eq!(text(&editor), "x\n");
+        type_keys(&mut editor, "3iy<Esc>");
+        assert_eq!(text(&editor), "xyyy\n");
+    }
+
+    #[test]
+    fn huge_hex_counts_move_to_the_ends() {
+        let mut editor = editor(&"0123456789abcdef".repeat(4));
+        type_keys(&mut editor, ":hex<CR>j");
+        let cursor = |editor: &Editor| editor.buffer.hex.as_ref().unwrap().cursor;
+        assert_eq!(cursor(&editor), ROW_BYTES);
+        type_keys(&mut editor, "999999999999999999j");
+        assert_eq!(cursor(&editor), 63);
+        type_keys(&mut editor, "99999999999999999999k");
+        assert_eq!(cursor(&editor), 0);
+        type_keys(&mut editor, "99999999999999999999l");
+        assert_eq!(cursor(&editor), 63);
+    }
+
+    #[test]
+    fn paste_after_keeps_grapheme_clusters_whole() {
+        let mut charwise = editor("e\u{301}x\n");
+        type_keys(&mut charwise, "lyl0p");
+        assert_eq!(text(&charwise), "e\u{301}xx\n");
+        let mut block = editor("e\u{301}a\ne\u{301}b\n");
+        type_keys(&mut block, "l<C-v>jy0p");
+        assert_eq!(text(&block), "e\u{301}aa\ne\u{301}bb\n");
+    }
+
+    #[test]
+    fn buffer_list() {
+        let dir = std::env::temp_dir().join(format!("rune-buffers-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        fs::write(path("one.txt"), "1\n2\n3\n").unwrap();
+        fs::write(path("two.txt"), "two\n").unwrap();
+        fs::write(path("three.txt"), "three\n").unwrap();
+        let mut editor = Editor::new(Config {
+            swap_file: false,
+            undo_file: false,
+            ..Config::default()
+        });
+        editor.terminal_size = (80, 24);
+        let name = |editor: &Editor| editor.buffer.path.clone().unwrap_or_default();
+
+        editor.open_file(&path("one.txt")).unwrap();
+        type_keys(&mut editor, "2j");
+        type_keys(&mut editor, &format!(":e {}<CR>", path("two.txt")));
+        type_keys(&mut editor, &format!(":e {}<CR>", path("three.txt")));
+        type_keys(&mut editor, ":ls<CR>");
+        let lines: Vec<&str> = editor.status_message.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", editor.status_message);
+        assert!(lines[0].ends_with("one.txt\" line 3"), "{}", lines[0]);
+        assert!(lines[2].contains("%a"), "{}", lines[2]);
+
+        type_keys(&mut editor, ":bn<CR>");
+        assert_eq!(name(&editor), path("one.txt"));
+        assert_eq!(editor.cursor.0, 2);
+        type_keys(&mut editor, ":bp<CR>");
+        assert_eq!(name(&editor), path("three.txt"));
+        type_keys(&mut editor, ":b two<CR>");
+        assert_eq!(name(&editor), path("two.txt"));
+        type_keys(&mut editor, ":b 99<CR>");
+        assert_eq!(editor.status_message, "Buffer 99 does not exist");
+        type_keys(&mut editor, ":b .txt<CR>");
+        assert_eq!(editor.status_message, "More than one match for .txt");
+
+        type_keys(&mut editor, "i!<Esc>");
+        type_keys(&mut editor, &format!(":e {}<CR>", path("one.txt")));
+        assert_eq!(editor.status_message, NOT_SAVED);
+        assert_eq!(name(&editor), path("two.txt"));
+        type_keys(&mut editor, ":bd<CR>");
+        assert!(
+            editor
+                .status_message
+                .starts_with("No write since last change")
+        );
+        assert_eq!(name(&editor), path("two.txt"));
+        type_keys(&mut editor, ":bd!<CR>:ls<CR>");
+        assert_eq!(editor.status_message.lines().count(), 2);
+        assert!(!editor.status_message.contains("two.txt"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn undo_offsets() {
+        assert!(matches!(parse_undo_offset("3"), Some(UndoOffset::Steps(3))));
+        assert!(matches!(
+            parse_undo_offset("2h"),
+            Some(UndoOffset::Time(t)) if t == Duration::from_secs(7200)
+        ));
+        assert!(parse_undo_offset("10x").is_none());
+        assert!(parse_undo_offset("m").is_none());
+        assert!(parse_undo_offset("999999999999999999d").is_none());
+    }
 }
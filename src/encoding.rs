@@ -0,0 +1,232 @@
+use std::borrow::Cow;
+
+/// How lines end in the file (`'fileformat'`). Buffers always use `\n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileFormat {
+    #[default]
+    Unix,
+    /// `\r\n`
+    Dos,
+    /// `\r`
+    Mac,
+}
+
+impl FileFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            "mac" => Some(FileFormat::Mac),
+            _ => None,
+        }
+    }
+
+    /// Dos when every line ends in `\r\n`, Mac when lines end in a bare `\r`
+    /// and Unix otherwise, so a file with mixed endings keeps its `\r`s.
+    pub fn detect(text: &str) -> Self {
+        let newlines = text.matches('\n').count();
+        if newlines == 0 {
+            return if text.contains('\r') {
+                FileFormat::Mac
+            } else {
+                FileFormat::Unix
+            };
+        }
+        if text.matches("\r\n").count() == newlines {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        }
+    }
+
+    /// Converts file text to buffer text.
+    pub fn to_buffer(self, text: String) -> String {
+        match self {
+            FileFormat::Unix => text,
+            FileFormat::Dos => text.replace("\r\n", "\n"),
+            FileFormat::Mac => text.replace('\r', "\n"),
+        }
+    }
+
+    /// Converts buffer text to file text.
+    pub fn to_file(self, text: &str) -> Cow<'_, str> {
+        match self {
+            FileFormat::Unix => Cow::Borrowed(text),
+            FileFormat::Dos => Cow::Owned(text.replace('\n', "\r\n")),
+            FileFormat::Mac => Cow::Owned(text.replace('\n', "\r")),
+        }
+    }
+}
+
+/// Character encoding of the file (`'fileencoding'`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Some(Encoding::Utf8Bom),
+            "utf-16le" | "utf16le" | "ucs-2le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" | "utf-16" | "ucs-2" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Decodes file contents, recognising byte order marks. Anything that is
+    /// not valid UTF-8 is read as Latin-1, which maps every byte to a char
+    /// and so writes back unchanged.
+    pub fn decode(bytes: &[u8]) -> (String, Self) {
+        if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF")
+            && let Ok(text) = std::str::from_utf8(rest)
+        {
+            return (text.to_string(), Encoding::Utf8Bom);
+        }
+        for (bom, encoding) in [
+            (b"\xFF\xFE", Encoding::Utf16Le),
+            (b"\xFE\xFF", Encoding::Utf16Be),
+        ] {
+            if let Some(rest) = bytes.strip_prefix(bom)
+                && let Some(text) = decode_utf16(rest, encoding == Encoding::Utf16Le)
+            {
+                return (text, encoding);
+            }
+        }
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), Encoding::Utf8),
+            Err(_) => (bytes.iter().map(|&b| b as char).collect(), Encoding::Latin1),
+        }
+    }
+
+    /// Byte order mark written at the start of the file.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+            Encoding::Utf8 | Encoding::Latin1 => b"",
+        }
+    }
+
+    /// A char in `text` this encoding cannot represent.
+    pub fn unencodable(self, mut text: impl Iterator<Item = char>) -> Option<char> {
+        match self {
+            Encoding::Latin1 => text.find(|&c| u32::from(c) > 0xFF),
+            _ => None,
+        }
+    }
+
+    /// Encodes a piece of text, without the byte order mark. Chars Latin-1
+    /// cannot hold become `?`; check with `unencodable` first.
+    pub fn encode(self, text: &str) -> Cow<'_, [u8]> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => Cow::Borrowed(text.as_bytes()),
+            Encoding::Utf16Le => {
+                Cow::Owned(text.encode_utf16().flat_map(u16::to_le_bytes).collect())
+            }
+            Encoding::Utf16Be => {
+                Cow::Owned(text.encode_utf16().flat_map(u16::to_be_bytes).collect())
+            }
+            Encoding::Latin1 => Cow::Owned(
+                text.chars()
+                    .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], little_endian: bool) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|pair| {
+        let pair = [pair[0], pair[1]];
+        if little_endian {
+            u16::from_le_bytes(pair)
+        } else {
+            u16::from_be_bytes(pair)
+        }
+    });
+    char::decode_utf16(units).collect::<Result<_, _>>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What saving a buffer writes: the BOM, then the text with its line
+    /// endings converted and encoded.
+    fn write(text: &str, format: FileFormat, encoding: Encoding) -> Vec<u8> {
+        let mut bytes = encoding.bom().to_vec();
+        bytes.extend_from_slice(&encoding.encode(&format.to_file(text)));
+        bytes
+    }
+
+    #[test]
+    fn files_read_back_as_written() {
+        let formats = [FileFormat::Unix, FileFormat::Dos, FileFormat::Mac];
+        let encodings = [
+            Encoding::Utf8,
+            Encoding::Utf8Bom,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+        ];
+        for format in formats {
+            for encoding in encodings {
+                let bytes = write("héllo\nwörld 😀\n", format, encoding);
+                let (text, decoded) = Encoding::decode(&bytes);
+                assert_eq!(decoded, encoding);
+                let detected = FileFormat::detect(&text);
+                assert_eq!(detected, format, "{:?}", encoding);
+                assert_eq!(detected.to_buffer(text), "héllo\nwörld 😀\n");
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_latin1_and_writes_back_unchanged() {
+        let bytes = b"caf\xe9\r\nna\xefve\r\n".to_vec();
+        let (text, encoding) = Encoding::decode(&bytes);
+        assert_eq!(encoding, Encoding::Latin1);
+        assert_eq!(text, "café\r\nnaïve\r\n");
+        let format = FileFormat::detect(&text);
+        assert_eq!(format, FileFormat::Dos);
+        let buffer = format.to_buffer(text);
+        assert_eq!(write(&buffer, format, encoding), bytes);
+        assert_eq!(encoding.unencodable("x€".chars()), Some('€'));
+    }
+
+    #[test]
+    fn mixed_line_endings_keep_their_carriage_returns() {
+        assert_eq!(FileFormat::detect("a\r\nb\n"), FileFormat::Unix);
+        assert_eq!(FileFormat::detect("a\rb"), FileFormat::Mac);
+        assert_eq!(FileFormat::detect("ab"), FileFormat::Unix);
+    }
+}
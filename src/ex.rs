@@ -0,0 +1,638 @@
+use crate::buffer::Buffer;
+use crate::encoding::{Encoding, FileFormat};
+use crate::search::{self, split_delimited};
+use regex::{Captures, Regex};
+use ropey::Rope;
+use std::ops::Range;
+
+/// The base of a line address in an Ex range, resolved later by the editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// `N`: a 1-based line number.
+    Line(usize),
+    /// `.`
+    Current,
+    /// `$`
+    Last,
+    /// `'x`, including the Visual marks `'<` and `'>`.
+    Mark(char),
+    /// `/pat/` searches forward from the next line, `?pat?` backward.
+    Pattern { pattern: String, forward: bool },
+}
+
+/// An address plus its `+N` / `-N` adjustments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineSpec {
+    pub base: Address,
+    pub offset: isize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExRange {
+    /// No range given; the command picks its default.
+    Default,
+    /// `%`
+    All,
+    Line(LineSpec),
+    /// `a,b`, or `a;b` when `set_current` makes `a` the current line before `b`
+    /// is resolved.
+    Span {
+        start: LineSpec,
+        end: LineSpec,
+        set_current: bool,
+    },
+}
+
+/// Splits the range off the front of an Ex command line.
+pub fn parse_range(input: &str) -> Result<(ExRange, &str), String> {
+    let input = input.trim_start();
+    if let Some(rest) = input.strip_prefix('%') {
+        return Ok((ExRange::All, rest));
+    }
+    let (start, rest) = parse_address(input)?;
+    let set_current = rest.starts_with(';');
+    let Some(after) = rest.strip_prefix([',', ';']) else {
+        return Ok((start.map_or(ExRange::Default, ExRange::Line), rest));
+    };
+    let (end, rest) = parse_address(after)?;
+    let current = || LineSpec {
+        base: Address::Current,
+        offset: 0,
+    };
+    let range = ExRange::Span {
+        start: start.unwrap_or_else(current),
+        end: end.unwrap_or_else(current),
+        set_current,
+    };
+    Ok((range, rest))
+}
+
+fn parse_address(input: &str) -> Result<(Option<LineSpec>, &str), String> {
+    let input = input.trim_start();
+    let mut chars = input.chars();
+    let (base, mut rest) = match chars.next() {
+        Some(c) if c.is_ascii_digit() => {
+            let end = input
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(input.len());
+            let n = input[..end].parse().map_err(|_| "Invalid range")?;
+            (Some(Address::Line(n)), &input[end..])
+        }
+        Some('.') => (Some(Address::Current), &input[1..]),
+        Some('$') => (Some(Address::Last), &input[1..]),
+        Some('\'') => {
+            let mark = chars.next().ok_or("Invalid range")?;
+            (Some(Address::Mark(mark)), &input[1 + mark.len_utf8()..])
+        }
+        Some(delimiter @ ('/' | '?')) => {
+            let (pattern, rest) = split_delimited(&input[1..], delimiter);
+            let forward = delimiter == '/';
+            (
+                Some(Address::Pattern { pattern, forward }),
+                rest.unwrap_or(""),
+            )
+        }
+        _ => (None, input),
+    };
+
+    let mut offset = 0;
+    let mut has_offset = false;
+    while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+        let digits = rest[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + 1);
+        let amount = match &rest[1..digits] {
+            "" => 1,
+            n => n.parse::<isize>().map_err(|_| "Invalid range")?,
+        };
+        offset += if sign == '+' { amount } else { -amount };
+        has_offset = true;
+        rest = &rest[digits..];
+    }
+
+    let spec = match base {
+        Some(base) => Some(LineSpec { base, offset }),
+        // A bare `+N` counts from the current line.
+        None if has_offset => Some(LineSpec {
+            base: Address::Current,
+            offset,
+        }),
+        None => None,
+    };
+    Ok((spec, rest))
+}
+
+/// Splits an Ex command (after its range) into its name and arguments. Names
+/// are a run of letters, or a single symbol such as `&`.
+pub fn split_command(input: &str) -> (&str, &str) {
+    let input = input.trim_start();
+    let end = match input.find(|c: char| !c.is_ascii_alphabetic()) {
+        Some(0) => input.chars().next().map_or(0, char::len_utf8),
+        Some(end) => end,
+        None => input.len(),
+    };
+    (&input[..end], &input[end..])
+}
+
+/// `++ff=` / `++enc=` options ahead of a file name, as in
+/// `:w ++enc=latin1 file`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileOptions {
+    pub fileformat: Option<FileFormat>,
+    pub encoding: Option<Encoding>,
+}
+
+/// Splits leading `++opt=value` arguments off `args`, returning them and the
+/// rest.
+pub fn parse_file_options(args: &str) -> Result<(FileOptions, &str), String> {
+    let mut options = FileOptions::default();
+    let mut rest = args.trim_start();
+    while let Some(arg) = rest.strip_prefix("++") {
+        let end = arg.find(char::is_whitespace).unwrap_or(arg.len());
+        let (name, value) = arg[..end].split_once('=').unwrap_or((&arg[..end], ""));
+        match name {
+            "ff" | "fileformat" => {
+                options.fileformat = Some(
+                    FileFormat::parse(value)
+                        .ok_or_else(|| format!("E474: Invalid argument: ++{}", &arg[..end]))?,
+                )
+            }
+            "enc" | "encoding" => {
+                options.encoding = Some(
+                    Encoding::parse(value)
+                        .ok_or_else(|| format!("E474: Invalid argument: ++{}", &arg[..end]))?,
+                )
+            }
+            _ => return Err(format!("E474: Invalid argument: ++{}", &arg[..end])),
+        }
+        rest = arg[end..].trim_start();
+    }
+    Ok((options, rest))
+}
+
+/// Parses the `[x] [count]` arguments of `:d` and `:y`: a register name
+/// unless the first char is a digit, then a count.
+pub fn parse_register_count(args: &str) -> Result<(Option<char>, Option<usize>), String> {
+    let args = args.trim();
+    let (register, rest) = match args.chars().next() {
+        Some(c) if !c.is_ascii_digit() => (Some(c), &args[c.len_utf8()..]),
+        _ => (None, args),
+    };
+    let count = match rest.trim() {
+        "" => None,
+        n => Some(
+            n.parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("E488: Trailing characters: {}", n))?,
+        ),
+    };
+    Ok((register, count))
+}
+
+/// Flags of a `:s` command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubstituteFlags {
+    /// `g`: every match on a line, not just the first.
+    pub global: bool,
+    /// `c`: ask before each substitution.
+    pub confirm: bool,
+    /// `i` / `I`: override `ignorecase`.
+    pub ignorecase: Option<bool>,
+}
+
+impl SubstituteFlags {
+    /// Parses `[&][gciI]... [count]`, starting from `previous` flags when the
+    /// input begins with `&`.
+    pub fn parse(input: &str, previous: SubstituteFlags) -> Result<(Self, Option<usize>), String> {
+        let input = input.trim();
+        let (mut flags, input) = match input.strip_prefix('&') {
+            Some(rest) => (previous, rest),
+            None => (SubstituteFlags::default(), input),
+        };
+        let count_at = input.find(|c: char| c.is_ascii_digit() || c.is_whitespace());
+        let (letters, count) = input.split_at(count_at.unwrap_or(input.len()));
+        for flag in letters.chars() {
+            match flag {
+                'g' => flags.global = true,
+                'c' => flags.confirm = true,
+                'i' => flags.ignorecase = Some(true),
+                'I' => flags.ignorecase = Some(false),
+                _ => return Err(format!("Invalid flag: {}", flag)),
+            }
+        }
+        let count = match count.trim() {
+            "" => None,
+            n => Some(
+                n.parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("Invalid count: {}", n))?,
+            ),
+        };
+        Ok((flags, count))
+    }
+}
+
+/// The last `:s`, repeated by `:&` and `&`.
+#[derive(Debug, Clone)]
+pub struct LastSubstitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: SubstituteFlags,
+}
+
+/// Splits `/pat/rep/flags` into pattern, replacement and the remaining flags.
+/// Returns `None` when the input does not start with a delimiter, meaning the
+/// previous pattern and replacement should be reused.
+pub fn parse_substitute(input: &str) -> Option<(String, String, &str)> {
+    let delimiter = input.chars().next().filter(|c| {
+        !c.is_alphanumeric() && !c.is_whitespace() && !matches!(c, '\\' | '"' | '|' | '&')
+    })?;
+    let (pattern, rest) = split_delimited(&input[delimiter.len_utf8()..], delimiter);
+    let (replacement, rest) = split_delimited(rest.unwrap_or(""), delimiter);
+    Some((pattern, replacement, rest.unwrap_or("")))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+/// Expands a `:s` replacement for one match: `&` and `\0` insert the whole
+/// match, `\1`-`\9` capture groups, `\u` / `\l` change the case of the next
+/// char, `\U` / `\L` of everything up to `\E` or `\e`, and `\r` / `\n` insert a
+/// line break.
+pub fn expand_replacement(replacement: &str, caps: &Captures) -> String {
+    let mut out = String::new();
+    let mut next_case = None;
+    let mut case = None;
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => {
+                let text = caps.get(0).map_or("", |m| m.as_str());
+                push_cased(&mut out, text, &mut next_case, case);
+            }
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => {
+                    let group = d as usize - '0' as usize;
+                    let text = caps.get(group).map_or("", |m| m.as_str());
+                    push_cased(&mut out, text, &mut next_case, case);
+                }
+                Some('u') => next_case = Some(Case::Upper),
+                Some('l') => next_case = Some(Case::Lower),
+                Some('U') => case = Some(Case::Upper),
+                Some('L') => case = Some(Case::Lower),
+                Some('E' | 'e') => case = None,
+                Some('r' | 'n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some(other) => push_cased(
+                    &mut out,
+                    other.encode_utf8(&mut [0; 4]),
+                    &mut next_case,
+                    case,
+                ),
+                None => out.push('\\'),
+            },
+            _ => push_cased(&mut out, c.encode_utf8(&mut [0; 4]), &mut next_case, case),
+        }
+    }
+    out
+}
+
+/// Appends `text`, changing case as requested by a pending `\u` / `\l` or
+/// an active `\U` / `\L`.
+fn push_cased(out: &mut String, text: &str, next_case: &mut Option<Case>, case: Option<Case>) {
+    for c in text.chars() {
+        match next_case.take().or(case) {
+            Some(Case::Upper) => out.extend(c.to_uppercase()),
+            Some(Case::Lower) => out.extend(c.to_lowercase()),
+            None => out.push(c),
+        }
+    }
+}
+
+/// A `:s` in progress over lines `row..=last`, stepping from match to match so
+/// confirm mode can pause between them.
+pub struct Substitution {
+    pub regex: Regex,
+    pub replacement: String,
+    pub global: bool,
+    pub row: usize,
+    pub col: usize,
+    pub last: usize,
+    /// Substitutions made and lines they were made on.
+    pub count: usize,
+    pub lines: usize,
+    pub last_row: Option<usize>,
+    /// The position directly follows a non-empty match, where an empty match
+    /// is not allowed.
+    after_match: bool,
+}
+
+impl Substitution {
+    pub fn new(regex: Regex, replacement: String, global: bool, first: usize, last: usize) -> Self {
+        Self {
+            regex,
+            replacement,
+            global,
+            row: first,
+            col: 0,
+            last,
+            count: 0,
+            lines: 0,
+            last_row: None,
+            after_match: false,
+        }
+    }
+
+    /// Finds the next match at or after the current position, as its row and
+    /// char columns.
+    pub fn next_match(&mut self, text: &Rope) -> Option<(usize, Range<usize>)> {
+        while self.row <= self.last && self.row < text.len_lines() {
+            let (line, content) = search::line_text(text, self.row);
+            let mut col = self.col;
+            while col <= line.len_chars() {
+                let Some(m) = self.regex.find_at(&content, line.char_to_byte(col)) else {
+                    break;
+                };
+                let range = line.byte_to_char(m.start())..line.byte_to_char(m.end());
+                if range.is_empty() && self.after_match && range.start == self.col {
+                    col = range.start + 1;
+                    continue;
+                }
+                self.col = range.start;
+                return Some((self.row, range));
+            }
+            self.advance_line();
+        }
+        None
+    }
+
+    /// Replaces match `m` on `row`, as found by `next_match`.
+    pub fn replace(&mut self, buffer: &mut Buffer, row: usize, m: Range<usize>) {
+        let text = {
+            let (line, content) = search::line_text(&buffer.content, row);
+            match self.regex.captures_at(&content, line.char_to_byte(m.start)) {
+                Some(caps) => expand_replacement(&self.replacement, &caps),
+                None => return,
+            }
+        };
+        let start = buffer.content.line_to_char(row) + m.start;
+        buffer.remove(start..start + m.len());
+        buffer.insert(start, &text);
+
+        self.count += 1;
+        if self.last_row != Some(row) {
+            self.lines += 1;
+        }
+        // Line breaks in the replacement push the remaining lines down.
+        let breaks = text.matches('\n').count();
+        let end_col = match text.rfind('\n') {
+            Some(i) => text[i + 1..].chars().count(),
+            None => m.start + text.chars().count(),
+        };
+        self.last += breaks;
+        self.last_row = Some(row + breaks);
+        self.row = row + breaks;
+        self.step_past(end_col, m.is_empty());
+    }
+
+    /// Leaves match `m` unchanged and moves past it.
+    pub fn skip(&mut self, m: Range<usize>) {
+        self.step_past(m.end, m.is_empty());
+    }
+
+    fn step_past(&mut self, col: usize, empty: bool) {
+        if !self.global {
+            self.advance_line();
+            return;
+        }
+        // An empty match consumes the following char so the search advances.
+        self.col = col + empty as usize;
+        self.after_match = !empty;
+    }
+
+    fn advance_line(&mut self) {
+        self.row += 1;
+        self.col = 0;
+        self.after_match = false;
+    }
+}
+
+/// A line marked by `:g`, followed through the edits made by the commands run
+/// on earlier lines. `end` is where the line's break (or the text) ends.
+#[derive(Debug, Clone, Copy)]
+pub struct LineAnchor {
+    pub start: usize,
+    end: usize,
+}
+
+impl LineAnchor {
+    pub fn new(text: &Rope, row: usize) -> Self {
+        let start = text.line_to_char(row);
+        let (line, _) = search::line_text(text, row);
+        Self {
+            start,
+            end: start + line.len_chars(),
+        }
+    }
+
+    /// Follows an edit replacing `removed` chars at `pos` with `inserted` ones
+    /// in a text of `len` chars. Returns `None` once the line is deleted.
+    pub fn shift(self, pos: usize, removed: usize, inserted: usize, len: usize) -> Option<Self> {
+        let deleted = removed > 0
+            && if self.end < len {
+                (pos..pos + removed).contains(&self.end)
+            } else {
+                // The last line goes together with the break before it.
+                pos < self.start && pos + removed >= self.end
+            };
+        if deleted {
+            return None;
+        }
+        let map = |idx: usize| {
+            if idx >= pos + removed {
+                idx - removed + inserted
+            } else {
+                idx.min(pos)
+            }
+        };
+        Some(Self {
+            start: map(self.start),
+            end: map(self.end),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(n: usize, offset: isize) -> LineSpec {
+        LineSpec {
+            base: Address::Line(n),
+            offset,
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("d").unwrap(), (ExRange::Default, "d"));
+        assert_eq!(parse_range("%s/a/b/").unwrap(), (ExRange::All, "s/a/b/"));
+        assert_eq!(parse_range("3").unwrap(), (ExRange::Line(line(3, 0)), ""));
+        assert_eq!(
+            parse_range("2,5+1-3d").unwrap(),
+            (
+                ExRange::Span {
+                    start: line(2, 0),
+                    end: line(5, -2),
+                    set_current: false,
+                },
+                "d"
+            )
+        );
+        let (range, rest) = parse_range("/a\\/b/;$y").unwrap();
+        assert_eq!(rest, "y");
+        assert_eq!(
+            range,
+            ExRange::Span {
+                start: LineSpec {
+                    base: Address::Pattern {
+                        pattern: String::from("a/b"),
+                        forward: true,
+                    },
+                    offset: 0,
+                },
+                end: LineSpec {
+                    base: Address::Last,
+                    offset: 0,
+                },
+                set_current: true,
+            }
+        );
+        let (range, _) = parse_range("'<,'>").unwrap();
+        assert!(matches!(
+            range,
+            ExRange::Span {
+                start: LineSpec {
+                    base: Address::Mark('<'),
+                    ..
+                },
+                ..
+            }
+        ));
+        // A bare offset counts from the current line, as does a missing end.
+        assert_eq!(
+            parse_range("+2,").unwrap().0,
+            ExRange::Span {
+                start: LineSpec {
+                    base: Address::Current,
+                    offset: 2,
+                },
+                end: LineSpec {
+                    base: Address::Current,
+                    offset: 0,
+                },
+                set_current: false,
+            }
+        );
+        assert!(parse_range("99999999999999999999999").is_err());
+    }
+
+    #[test]
+    fn commands_and_arguments() {
+        assert_eq!(split_command(" delete a 3"), ("delete", " a 3"));
+        assert_eq!(split_command("&&"), ("&", "&"));
+        assert_eq!(split_command("s/a/b/"), ("s", "/a/b/"));
+        assert_eq!(parse_register_count(""), Ok((None, None)));
+        assert_eq!(parse_register_count(" a"), Ok((Some('a'), None)));
+        assert_eq!(parse_register_count("3"), Ok((None, Some(3))));
+        assert_eq!(parse_register_count("a 3"), Ok((Some('a'), Some(3))));
+        assert_eq!(parse_register_count("_2"), Ok((Some('_'), Some(2))));
+        assert!(parse_register_count("a b").is_err());
+        assert!(parse_register_count("0").is_err());
+        let (options, rest) = parse_file_options("++ff=dos ++enc=latin1 out.txt").unwrap();
+        assert_eq!(options.fileformat, Some(FileFormat::Dos));
+        assert_eq!(options.encoding, Some(Encoding::Latin1));
+        assert_eq!(rest, "out.txt");
+        assert!(parse_file_options("++ff=amiga").is_err());
+    }
+
+    #[test]
+    fn substitute_commands() {
+        assert_eq!(
+            parse_substitute("#a\\#b#c#gi"),
+            Some((String::from("a#b"), String::from("c"), "gi"))
+        );
+        assert_eq!(
+            parse_substitute("/a/"),
+            Some((String::from("a"), String::new(), ""))
+        );
+        assert_eq!(parse_substitute(" g"), None);
+
+        let previous = SubstituteFlags {
+            global: true,
+            ..SubstituteFlags::default()
+        };
+        let (flags, count) = SubstituteFlags::parse("&c 4", previous).unwrap();
+        assert!(flags.global && flags.confirm);
+        assert_eq!(count, Some(4));
+        let (flags, count) = SubstituteFlags::parse("I", previous).unwrap();
+        assert_eq!(
+            (flags.global, flags.ignorecase, count),
+            (false, Some(false), None)
+        );
+        assert!(SubstituteFlags::parse("x", previous).is_err());
+        assert!(SubstituteFlags::parse("g 0", previous).is_err());
+    }
+
+    #[test]
+    fn replacements() {
+        let regex = Regex::new(r"(\w+) (\w+)").unwrap();
+        let caps = regex.captures("hello world").unwrap();
+        let expand = |replacement| expand_replacement(replacement, &caps);
+        assert_eq!(expand(r"\2 \1"), "world hello");
+        assert_eq!(expand("[&]"), "[hello world]");
+        assert_eq!(expand(r"\u\1 \U\2\E!"), "Hello WORLD!");
+        assert_eq!(expand(r"\L\0"), "hello world");
+        assert_eq!(expand(r"\1\r\2\t\&"), "hello\nworld\t&");
+    }
+
+    fn substitute(text: &str, pattern: &str, replacement: &str, global: bool) -> String {
+        let mut buffer = Buffer::from_str(text);
+        let last = text.lines().count() - 1;
+        let regex = Regex::new(pattern).unwrap();
+        let mut sub = Substitution::new(regex, replacement.to_string(), global, 0, last);
+        while let Some((row, m)) = sub.next_match(&buffer.content) {
+            sub.replace(&mut buffer, row, m);
+        }
+        buffer.content.to_string()
+    }
+
+    #[test]
+    fn substitutions() {
+        assert_eq!(substitute("aa\naa\n", "a", "b", false), "ba\nba\n");
+        assert_eq!(substitute("aa\naa\n", "a", "b", true), "bb\nbb\n");
+        // An empty match right after a replaced one is skipped, as in Vim.
+        assert_eq!(substitute("abc\n", "x*", "-", true), "-a-b-c-\n");
+        assert_eq!(substitute("a,b\n", ",", r"\r", true), "a\nb\n");
+        assert_eq!(substitute("a b\nc\n", " ", r"\n", true), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn anchors_follow_edits() {
+        let text = Rope::from_str("one\ntwo\nthree\n");
+        let len = text.len_chars();
+        let two = LineAnchor::new(&text, 1);
+        assert_eq!(two.start, 4);
+        // Text inserted above moves the line down.
+        assert_eq!(two.shift(0, 0, 3, len).unwrap().start, 7);
+        // Deleting the line with its break drops it.
+        assert!(two.shift(4, 4, 0, len).is_none());
+        // Joining it onto the line above keeps it, at the join.
+        assert_eq!(two.shift(3, 1, 1, len).unwrap().start, 4);
+    }
+}
@@ -0,0 +1,489 @@
+use crate::config::Config;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Why a file could not be opened.
+#[derive(Debug)]
+pub enum OpenError {
+    PermissionDenied(String),
+    IsDirectory(String),
+    Io(String, io::Error),
+}
+
+impl OpenError {
+    pub fn new(path: &str, error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::PermissionDenied => OpenError::PermissionDenied(path.to_string()),
+            io::ErrorKind::IsADirectory => OpenError::IsDirectory(path.to_string()),
+            _ => OpenError::Io(path.to_string(), error),
+        }
+    }
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OpenError::PermissionDenied(path) => write!(f, "\"{}\" [Permission Denied]", path),
+            OpenError::IsDirectory(path) => write!(f, "\"{}\" is a directory", path),
+            OpenError::Io(path, e) => write!(f, "Can't open \"{}\": {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for OpenError {}
+
+/// What a file on disk looked like when it was last read or written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskState {
+    pub mtime: Option<SystemTime>,
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl DiskState {
+    pub fn new(meta: &Metadata, hash: u64) -> Self {
+        Self {
+            mtime: meta.modified().ok(),
+            len: meta.len(),
+            hash,
+        }
+    }
+
+    /// Reads the file in chunks, so large files are never held in memory.
+    pub fn read(path: &str) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let mut file = File::open(path).ok()?;
+        let mut hasher = Hasher::new();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            match file.read(&mut chunk).ok()? {
+                0 => break,
+                n => hasher.update(&chunk[..n]),
+            }
+        }
+        Some(Self::new(&meta, hasher.finish()))
+    }
+}
+
+pub enum DiskChange {
+    Unchanged,
+    Changed,
+    Deleted,
+}
+
+/// Compares `path` with how it was `recorded`. A file of another size has
+/// changed; one whose timestamp alone moved is hashed, so merely touching a
+/// file does not count as a change.
+pub fn check_disk(path: &str, recorded: &DiskState) -> DiskChange {
+    let Ok(meta) = fs::metadata(path) else {
+        return DiskChange::Deleted;
+    };
+    if meta.len() != recorded.len {
+        return DiskChange::Changed;
+    }
+    if meta.modified().ok() == recorded.mtime {
+        return DiskChange::Unchanged;
+    }
+    match DiskState::read(path) {
+        Some(state) if state.hash != recorded.hash => DiskChange::Changed,
+        Some(_) => DiskChange::Unchanged,
+        None => DiskChange::Deleted,
+    }
+}
+
+/// Whether this process may write to the existing file at `path`. Opening it
+/// for writing, without truncating, takes permissions, ACLs and read-only
+/// mounts into account.
+pub fn is_writable(path: &str) -> bool {
+    OpenOptions::new().write(true).open(path).is_ok()
+}
+
+/// Size of the pieces files are read in.
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// FNV-1a over file contents fed in pieces, as for undo files.
+pub struct Hasher(u64);
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Contents to save, written out piece by piece.
+pub type Contents<'a> = &'a dyn Fn(&mut dyn Write) -> io::Result<()>;
+
+/// Writes `data` to `path` as configured: after backing up the old file, and
+/// through a temporary file renamed into place unless that would break a
+/// link, the directory does not allow it or the file's owner cannot be kept.
+/// Returns the state of the file written, hashed as it was written.
+pub fn write_file(path: &str, data: Contents, config: &Config) -> io::Result<DiskState> {
+    let path = Path::new(path);
+    let existing = fs::symlink_metadata(path).ok();
+    if config.backup && existing.is_some() {
+        let backup = backup_path(path, config)?;
+        fs::copy(path, &backup).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("cannot write backup {}: {}", backup.display(), e),
+            )
+        })?;
+    }
+
+    if config.atomic_save && !existing.as_ref().is_some_and(is_linked) {
+        // Follow the metadata through to the file a rename would replace.
+        let target = fs::metadata(path).ok();
+        match write_atomic(path, data, target.as_ref(), config.fsync) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+            result => return result.and_then(|hash| written(path, hash)),
+        }
+    }
+    let hash = write_in_place(path, data, config.fsync)?;
+    written(path, hash)
+}
+
+fn written(path: &Path, hash: u64) -> io::Result<DiskState> {
+    Ok(DiskState::new(&fs::metadata(path)?, hash))
+}
+
+/// Renaming over a symlink would replace the link itself, and over a hard
+/// link would split the file from its other names; these are overwritten in
+/// place instead.
+fn is_linked(meta: &Metadata) -> bool {
+    meta.file_type().is_symlink() || link_count(meta) > 1
+}
+
+#[cfg(unix)]
+fn link_count(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_meta: &Metadata) -> u64 {
+    1
+}
+
+fn write_atomic(
+    path: &Path,
+    data: Contents,
+    existing: Option<&Metadata>,
+    sync: bool,
+) -> io::Result<u64> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let temp = dir.join(format!(
+        ".{}.rune-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    // A crash may have left one behind.
+    let _ = fs::remove_file(&temp);
+
+    let result = (|| {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        if let Some(meta) = existing {
+            create_mode(&mut options, meta);
+        }
+        let mut file = options.open(&temp)?;
+        // Ownership and mode are settled before any contents are written, so
+        // the text of a private file is never readable by others.
+        if let Some(meta) = existing {
+            preserve_owner(&temp, meta)?;
+            fs::set_permissions(&temp, meta.permissions())?;
+        }
+        let hash = write_contents(&mut file, data)?;
+        if sync {
+            file.sync_all()?;
+        }
+        fs::rename(&temp, path)?;
+        Ok(hash)
+    })();
+    match result {
+        Ok(_) if sync => {
+            // The rename itself is only durable once the directory is.
+            if let Ok(dir) = File::open(dir) {
+                let _ = dir.sync_all();
+            }
+        }
+        Ok(_) => {}
+        Err(_) => {
+            let _ = fs::remove_file(&temp);
+        }
+    }
+    result
+}
+
+/// Creates the temporary file with the original's mode (less the umask)
+/// rather than the default, which may let others read it.
+#[cfg(unix)]
+fn create_mode(options: &mut OpenOptions, meta: &Metadata) {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    options.mode(meta.permissions().mode() & 0o777);
+}
+
+#[cfg(not(unix))]
+fn create_mode(_options: &mut OpenOptions, _meta: &Metadata) {}
+
+/// Writes `contents` to `path`, a file kept about `original` such as its swap
+/// file, with `original`'s permission bits (owner-only while it does not exist)
+/// so the text is no easier to read there than in the file itself.
+pub fn write_restricted(path: &Path, contents: &str, original: &str) -> io::Result<()> {
+    open_restricted(path, original)?.write_all(contents.as_bytes())
+}
+
+#[cfg(unix)]
+fn open_restricted(path: &Path, original: &str) -> io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let mode = fs::metadata(original).map_or(0o600, |meta| meta.permissions().mode() & 0o777);
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+    // A file that was already there keeps its old mode, and a new one is
+    // subject to the umask, until set here.
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn open_restricted(path: &Path, _original: &str) -> io::Result<File> {
+    File::create(path)
+}
+
+/// Gives the new file the original's owner and group. Only root may give a
+/// file away; when that fails the error is returned, so the save falls back
+/// to overwriting the file in place, which keeps both.
+#[cfg(unix)]
+fn preserve_owner(path: &Path, meta: &Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    let created = fs::metadata(path)?;
+    let uid = (created.uid() != meta.uid()).then_some(meta.uid());
+    let gid = (created.gid() != meta.gid()).then_some(meta.gid());
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(path, uid, gid).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("cannot keep the file's owner: {}", e),
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn preserve_owner(_path: &Path, _meta: &Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+fn write_in_place(path: &Path, data: Contents, sync: bool) -> io::Result<u64> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    let hash = write_contents(&mut file, data)?;
+    if sync {
+        file.sync_all()?;
+    }
+    Ok(hash)
+}
+
+/// Writes `data` to `file`, returning the hash of the bytes written.
+fn write_contents(file: &mut File, data: Contents) -> io::Result<u64> {
+    let mut out = Hashing {
+        inner: BufWriter::with_capacity(CHUNK_SIZE, file),
+        hasher: Hasher::new(),
+    };
+    data(&mut out)?;
+    out.inner.flush()?;
+    Ok(out.hasher.finish())
+}
+
+/// Passes writes through to `inner`, hashing them on the way.
+struct Hashing<W> {
+    inner: W,
+    hasher: Hasher,
+}
+
+impl<W: Write> Write for Hashing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `{file}.bak` beside the file, or in `backup_dir` named after the full path
+/// with `/` replaced by `%` so files from different directories don't clash.
+fn backup_path(path: &Path, config: &Config) -> io::Result<PathBuf> {
+    let Some(dir) = &config.backup_dir else {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".bak");
+        return Ok(PathBuf::from(name));
+    };
+    fs::create_dir_all(dir)?;
+    let canonical = fs::canonicalize(path)?;
+    let name = canonical.to_string_lossy().replace(['/', '\\'], "%");
+    Ok(Path::new(dir).join(name + ".bak"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rune-fileio-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn atomic_save_never_exposes_a_private_file() {
+        let dir = scratch_dir("mode");
+        let path = dir.join("secret");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+
+        let temp_modes = std::cell::RefCell::new(Vec::new());
+        let data = |out: &mut dyn Write| {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                if entry.file_name().to_string_lossy().ends_with(".tmp") {
+                    temp_modes
+                        .borrow_mut()
+                        .push(entry.metadata()?.permissions().mode() & 0o777);
+                }
+            }
+            out.write_all(b"new")
+        };
+        let state = write_file(path.to_str().unwrap(), &data, &Config::default()).unwrap();
+
+        assert_eq!(Some(state), DiskState::read(path.to_str().unwrap()));
+        assert_eq!(*temp_modes.borrow(), vec![0o600]);
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn atomic_save_keeps_the_owner() {
+        let dir = scratch_dir("owner");
+        let path = dir.join("theirs");
+        fs::write(&path, "old").unwrap();
+        let owner = fs::metadata(&path).unwrap();
+        // Giving the file away needs root; elsewhere it already is ours.
+        let (uid, gid) = match std::os::unix::fs::chown(&path, Some(12345), Some(12345)) {
+            Ok(()) => (12345, 12345),
+            Err(_) => (owner.uid(), owner.gid()),
+        };
+        write_file(
+            path.to_str().unwrap(),
+            &|out| out.write_all(b"new"),
+            &Config::default(),
+        )
+        .unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        assert_eq!((meta.uid(), meta.gid()), (uid, gid));
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn check_disk_tells_changes_from_touches() {
+        let dir = scratch_dir("check");
+        let path = dir.join("log");
+        let config = Config {
+            atomic_save: false,
+            ..Config::default()
+        };
+        let state = write_file(
+            path.to_str().unwrap(),
+            &|out| out.write_all(b"one\n"),
+            &config,
+        )
+        .unwrap();
+        let path = path.to_str().unwrap();
+        assert_eq!(Some(state), DiskState::read(path));
+        assert!(matches!(check_disk(path, &state), DiskChange::Unchanged));
+
+        // Same contents under a new timestamp.
+        let touched = DiskState {
+            mtime: None,
+            ..state
+        };
+        assert!(matches!(check_disk(path, &touched), DiskChange::Unchanged));
+        let other = DiskState {
+            mtime: None,
+            hash: state.hash ^ 1,
+            ..state
+        };
+        assert!(matches!(check_disk(path, &other), DiskChange::Changed));
+
+        fs::OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(b"two\n")
+            .unwrap();
+        assert!(matches!(check_disk(path, &state), DiskChange::Changed));
+        fs::remove_file(path).unwrap();
+        assert!(matches!(check_disk(path, &state), DiskChange::Deleted));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restricted_files_take_the_original_mode() {
+        let dir = scratch_dir("restricted");
+        let original = dir.join("notes");
+        let kept = dir.join("notes.swp");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        // Not written yet: owner-only.
+        write_restricted(&kept, "one", original.to_str().unwrap()).unwrap();
+        assert_eq!(mode(&kept), 0o600);
+
+        fs::write(&original, "").unwrap();
+        fs::set_permissions(&original, fs::Permissions::from_mode(0o640)).unwrap();
+        write_restricted(&kept, "two", original.to_str().unwrap()).unwrap();
+        assert_eq!(mode(&kept), 0o640);
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "two");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::encoding::{Encoding, FileFormat};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// New text read from a followed file.
+pub enum FollowUpdate {
+    /// Bytes were appended to the file.
+    Appended(String),
+    /// The file was truncated or replaced (log rotation); this is all of it.
+    Reset(String),
+}
+
+/// Reads what is appended to a file being followed (`rune -f`, `:follow`).
+pub struct Follow {
+    /// Bytes of the file already in the buffer.
+    offset: u64,
+    /// Device and inode of the file, to notice it being replaced.
+    identity: Option<(u64, u64)>,
+    /// An incomplete UTF-8 sequence, or a `\r` that may start a `\r\n`, left
+    /// at the end of the last read.
+    carry: Vec<u8>,
+    encoding: Encoding,
+    fileformat: FileFormat,
+    /// `readonly` from before following started, restored when it stops.
+    pub was_readonly: bool,
+}
+
+impl Follow {
+    /// Follows the file at `path` from byte `offset` on, decoding it the way
+    /// the buffer was loaded. UTF-16 files cannot be followed.
+    pub fn new(
+        path: &str,
+        offset: u64,
+        encoding: Encoding,
+        fileformat: FileFormat,
+        was_readonly: bool,
+    ) -> Option<Self> {
+        if matches!(encoding, Encoding::Utf16Le | Encoding::Utf16Be) {
+            return None;
+        }
+        Some(Self {
+            offset,
+            identity: fs::metadata(path).ok().and_then(|meta| identity(&meta)),
+            carry: Vec::new(),
+            encoding,
+            fileformat,
+            was_readonly,
+        })
+    }
+
+    /// Reads whatever was added to the file since the last call. A file that
+    /// shrank or was replaced is read again from the start; one that is
+    /// missing, as between rotating a log and creating the next one, is
+    /// waited for.
+    pub fn poll(&mut self, path: &str) -> io::Result<Option<FollowUpdate>> {
+        let Ok(meta) = fs::metadata(path) else {
+            return Ok(None);
+        };
+        let reset = meta.len() < self.offset || identity(&meta) != self.identity;
+        if reset {
+            self.offset = 0;
+            self.identity = identity(&meta);
+            self.carry.clear();
+        } else if meta.len() == self.offset {
+            return Ok(None);
+        }
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        self.offset += bytes.len() as u64;
+        let mut new = bytes.as_slice();
+        if reset && self.encoding == Encoding::Utf8Bom {
+            new = new.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(new);
+        }
+        let text = self.decode(new);
+        Ok(Some(if reset {
+            FollowUpdate::Reset(text)
+        } else {
+            FollowUpdate::Appended(text)
+        }))
+    }
+
+    /// Decodes `bytes` after what was carried over from the last read, holding
+    /// back anything that may still be completed by the next one.
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        self.carry.extend_from_slice(bytes);
+        let mut complete = match self.encoding {
+            Encoding::Latin1 => self.carry.len(),
+            _ => match std::str::from_utf8(&self.carry) {
+                Ok(text) => text.len(),
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                // Invalid bytes are shown as replacement chars.
+                Err(_) => self.carry.len(),
+            },
+        };
+        if self.fileformat == FileFormat::Dos && self.carry[..complete].ends_with(b"\r") {
+            complete -= 1;
+        }
+        let text = match self.encoding {
+            Encoding::Latin1 => self.carry[..complete].iter().map(|&b| b as char).collect(),
+            _ => String::from_utf8_lossy(&self.carry[..complete]).into_owned(),
+        };
+        self.carry.drain(..complete);
+        self.fileformat.to_buffer(text)
+    }
+}
+
+#[cfg(unix)]
+fn identity(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
@@ -0,0 +1,214 @@
+/// Bytes shown per row.
+pub const ROW_BYTES: usize = 16;
+
+/// Screen column where the ASCII column starts: the offset, then the hex
+/// bytes in two groups of eight.
+pub const ASCII_COLUMN: usize = 10 + ROW_BYTES * 3 + 2;
+
+/// The raw bytes of a buffer, viewed and edited in hex mode (`:hex`). Editing
+/// overwrites bytes in place, so the file keeps its length.
+pub struct HexView {
+    pub bytes: Vec<u8>,
+    /// Offset of the byte under the cursor.
+    pub cursor: usize,
+    /// The next hex digit typed goes into the low nibble of the cursor byte.
+    pub low_nibble: bool,
+    /// The cursor is in the ASCII column rather than the hex one.
+    pub ascii: bool,
+    /// First row shown.
+    pub scroll: usize,
+    /// Changed since the bytes were last written.
+    pub dirty: bool,
+    /// Changed since hex mode was entered.
+    pub edited: bool,
+    /// Offsets and old values of overwritten bytes, oldest first, for `u`.
+    undo: Vec<(usize, u8)>,
+}
+
+impl HexView {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes,
+            cursor: 0,
+            low_nibble: false,
+            ascii: false,
+            scroll: 0,
+            dirty: false,
+            edited: false,
+            undo: Vec::new(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bytes.len().div_ceil(ROW_BYTES).max(1)
+    }
+
+    /// Moves the cursor to byte `offset`, clamped to the data.
+    pub fn move_to(&mut self, offset: usize) {
+        self.cursor = offset.min(self.bytes.len().saturating_sub(1));
+        self.low_nibble = false;
+    }
+
+    pub fn move_by(&mut self, delta: isize) {
+        self.move_to(self.cursor.saturating_add_signed(delta));
+    }
+
+    /// Overwrites the nibble under the cursor with hex digit `digit`, moving
+    /// on to the next nibble.
+    pub fn type_nibble(&mut self, digit: u8) {
+        let Some(&old) = self.bytes.get(self.cursor) else {
+            return;
+        };
+        let value = if self.low_nibble {
+            (old & 0xF0) | digit
+        } else {
+            (old & 0x0F) | (digit << 4)
+        };
+        self.set_byte(value);
+        if self.low_nibble {
+            self.move_by(1);
+        } else {
+            self.low_nibble = true;
+        }
+    }
+
+    /// Overwrites the byte under the cursor, moving on to the next one.
+    pub fn type_byte(&mut self, value: u8) {
+        if self.cursor < self.bytes.len() {
+            self.set_byte(value);
+            self.move_by(1);
+        }
+    }
+
+    fn set_byte(&mut self, value: u8) {
+        let old = std::mem::replace(&mut self.bytes[self.cursor], value);
+        self.undo.push((self.cursor, old));
+        self.dirty = true;
+        self.edited = true;
+    }
+
+    /// Restores the byte overwritten last. Returns false when there is none.
+    pub fn undo(&mut self) -> bool {
+        let Some((offset, old)) = self.undo.pop() else {
+            return false;
+        };
+        self.bytes[offset] = old;
+        self.move_to(offset);
+        self.dirty = true;
+        true
+    }
+
+    /// Finds `pattern` after the cursor (or, backwards, before it), wrapping
+    /// around the end of the data. Returns its offset and whether the search
+    /// wrapped.
+    pub fn find(&self, pattern: &[u8], forward: bool) -> Option<(usize, bool)> {
+        if pattern.is_empty() || pattern.len() > self.bytes.len() {
+            return None;
+        }
+        let mut starts = self.bytes.windows(pattern.len()).enumerate();
+        let matches = |&(_, window): &(usize, &[u8])| window == pattern;
+        if forward {
+            let after = starts.clone().skip(self.cursor + 1).find(matches);
+            after
+                .map(|(i, _)| (i, false))
+                .or_else(|| starts.find(matches).map(|(i, _)| (i, true)))
+        } else {
+            let before = starts.clone().take(self.cursor).rfind(matches);
+            before
+                .map(|(i, _)| (i, false))
+                .or_else(|| starts.rfind(matches).map(|(i, _)| (i, true)))
+        }
+    }
+
+    /// Scrolls so the cursor row is among the `height` rows shown.
+    pub fn scroll_to_cursor(&mut self, height: usize) {
+        let row = self.cursor / ROW_BYTES;
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + height.max(1) {
+            self.scroll = row + 1 - height.max(1);
+        }
+    }
+
+    /// Screen column of the cursor within a row.
+    pub fn cursor_column(&self) -> usize {
+        let col = self.cursor % ROW_BYTES;
+        if self.ascii {
+            ASCII_COLUMN + col
+        } else {
+            10 + col * 3 + (col >= ROW_BYTES / 2) as usize + self.low_nibble as usize
+        }
+    }
+}
+
+/// Bytes to search for: hex digits after `0x`, optionally separated by
+/// spaces (`0xde ad be ef`), or else the text itself.
+pub fn parse_pattern(query: &str) -> Vec<u8> {
+    let Some(digits) = query.strip_prefix("0x") else {
+        return query.as_bytes().to_vec();
+    };
+    let digits: String = digits.chars().filter(|c| *c != ' ').collect();
+    let is_hex = !digits.is_empty()
+        && digits.len().is_multiple_of(2)
+        && digits.chars().all(|c| c.is_ascii_hexdigit());
+    if !is_hex {
+        return query.as_bytes().to_vec();
+    }
+    (0..digits.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// One row as shown: offset, hex bytes and their printable ASCII.
+pub fn format_row(bytes: &[u8], row: usize) -> String {
+    let start = row * ROW_BYTES;
+    let chunk = bytes
+        .get(start..(start + ROW_BYTES).min(bytes.len()))
+        .unwrap_or(&[]);
+    let mut line = format!("{:08x}  ", start);
+    for i in 0..ROW_BYTES {
+        match chunk.get(i) {
+            Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+            None => line.push_str("   "),
+        }
+        if i + 1 == ROW_BYTES / 2 {
+            line.push(' ');
+        }
+    }
+    line.push('|');
+    line.extend(chunk.iter().map(|&b| printable(b)));
+    line.push('|');
+    line
+}
+
+/// How byte `b` shows in the ASCII column.
+fn printable(b: u8) -> char {
+    if b.is_ascii_graphic() || b == b' ' {
+        b as char
+    } else {
+        '.'
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_are_bytes_only_after_0x() {
+        assert_eq!(parse_pattern("0xde ad BE ef"), vec![0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse_pattern("cafe"), b"cafe".to_vec());
+        assert_eq!(parse_pattern("0xabc"), b"0xabc".to_vec());
+        assert_eq!(parse_pattern("0x"), b"0x".to_vec());
+    }
+
+    #[test]
+    fn rows_show_offset_bytes_and_ascii() {
+        let bytes: Vec<u8> = (0x41..0x41 + 20).collect();
+        assert_eq!(
+            format_row(&bytes, 1),
+            format!("00000010  51 52 53 54 {}|QRST|", " ".repeat(12 * 3 + 1))
+        );
+    }
+}
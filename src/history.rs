@@ -0,0 +1,254 @@
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+
+/// A single primitive edit: `removed` was replaced by `inserted` at char
+/// index `pos`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    pub pos: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+/// One undoable change, made of the edits applied while it was in progress.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub edits: Vec<Edit>,
+    pub cursor_before: (usize, usize),
+    pub cursor_after: (usize, usize),
+    pub time: SystemTime,
+}
+
+impl Transaction {
+    pub fn apply(&self, content: &mut Rope) {
+        for edit in &self.edits {
+            let removed_len = edit.removed.chars().count();
+            content.remove(edit.pos..edit.pos + removed_len);
+            content.insert(edit.pos, &edit.inserted);
+        }
+    }
+
+    pub fn revert(&self, content: &mut Rope) {
+        for edit in self.edits.iter().rev() {
+            let inserted_len = edit.inserted.chars().count();
+            content.remove(edit.pos..edit.pos + inserted_len);
+            content.insert(edit.pos, &edit.removed);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    parent: usize,
+    /// Child that `redo` follows: the most recently created or revisited one.
+    redo_child: Option<usize>,
+    txn: Option<Transaction>,
+}
+
+/// Undo tree of transactions. Node 0 is the original text; every other node is
+/// the state after its transaction. Nodes are numbered in creation order, which
+/// `g-`/`g+` and `:earlier`/`:later` walk chronologically.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: 0,
+                redo_child: None,
+                txn: None,
+            }],
+            current: 0,
+        }
+    }
+
+    /// Number of the current state; 0 is the original text.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Number of the newest state.
+    pub fn latest(&self) -> usize {
+        self.nodes.len() - 1
+    }
+
+    /// Adds `txn` as a new branch below the current state.
+    pub fn push(&mut self, txn: Transaction) {
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.current,
+            redo_child: None,
+            txn: Some(txn),
+        });
+        self.nodes[self.current].redo_child = Some(id);
+        self.current = id;
+    }
+
+    /// Reverts the current transaction, returning the cursor from before it.
+    pub fn undo(&mut self, content: &mut Rope) -> Option<(usize, usize)> {
+        let node = &self.nodes[self.current];
+        let txn = node.txn.as_ref()?;
+        txn.revert(content);
+        let cursor = txn.cursor_before;
+        let (id, parent) = (self.current, node.parent);
+        self.nodes[parent].redo_child = Some(id);
+        self.current = parent;
+        Some(cursor)
+    }
+
+    /// Re-applies the most recent child transaction, returning the cursor from
+    /// after it.
+    pub fn redo(&mut self, content: &mut Rope) -> Option<(usize, usize)> {
+        let child = self.nodes[self.current].redo_child?;
+        let txn = self.nodes[child].txn.as_ref()?;
+        txn.apply(content);
+        self.current = child;
+        Some(txn.cursor_after)
+    }
+
+    /// Moves to state `target` across branches, returning the cursor of the
+    /// last transaction applied or reverted.
+    pub fn goto(&mut self, target: usize, content: &mut Rope) -> Option<(usize, usize)> {
+        if target >= self.nodes.len() || target == self.current {
+            return None;
+        }
+        let down = self.path_to_root(target);
+        let mut cursor = None;
+        while !down.contains(&self.current) {
+            cursor = self.undo(content);
+        }
+        let common = down.iter().position(|&id| id == self.current)?;
+        for &id in down[..common].iter().rev() {
+            self.nodes[self.current].redo_child = Some(id);
+            cursor = self.redo(content);
+        }
+        cursor
+    }
+
+    /// State `count` steps back (`g-`) or forward (`g+`) in creation order.
+    pub fn step(&self, count: isize) -> usize {
+        self.current.saturating_add_signed(count).min(self.latest())
+    }
+
+    /// Newest state created no later than `offset` before (`earlier`) or after
+    /// the time of the current state.
+    pub fn state_at(&self, offset: Duration, earlier: bool) -> usize {
+        let now = self.time_of(self.current).unwrap_or_else(SystemTime::now);
+        let limit = if earlier {
+            now.checked_sub(offset).unwrap_or(SystemTime::UNIX_EPOCH)
+        } else {
+            match now.checked_add(offset) {
+                Some(limit) => limit,
+                None => return self.latest(),
+            }
+        };
+        (1..self.nodes.len())
+            .rev()
+            .find(|&id| self.time_of(id).is_some_and(|time| time <= limit))
+            .unwrap_or(0)
+    }
+
+    fn time_of(&self, id: usize) -> Option<SystemTime> {
+        self.nodes[id].txn.as_ref().map(|txn| txn.time)
+    }
+
+    fn path_to_root(&self, mut id: usize) -> Vec<usize> {
+        let mut path = vec![id];
+        while id != 0 {
+            id = self.nodes[id].parent;
+            path.push(id);
+        }
+        path
+    }
+}
+
+/// Inserts `inserted` at `pos` in `text` and records it in `history` as made
+/// `secs` seconds after the epoch.
+#[cfg(test)]
+pub(crate) fn push_insert(
+    history: &mut History,
+    text: &mut Rope,
+    pos: usize,
+    inserted: &str,
+    secs: u64,
+) {
+    let txn = Transaction {
+        edits: vec![Edit {
+            pos,
+            removed: String::new(),
+            inserted: inserted.to_string(),
+        }],
+        cursor_before: (0, pos),
+        cursor_after: (0, pos + inserted.len()),
+        time: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+    };
+    txn.apply(text);
+    history.push(txn);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// States 1 and 2 on one branch and, after undoing 2, state 3 on another.
+    fn branched() -> (History, Rope) {
+        let mut text = Rope::from_str("a");
+        let mut history = History::new();
+        push_insert(&mut history, &mut text, 1, "b", 10);
+        push_insert(&mut history, &mut text, 2, "c", 20);
+        history.undo(&mut text);
+        push_insert(&mut history, &mut text, 2, "d", 30);
+        (history, text)
+    }
+
+    #[test]
+    fn undo_and_redo_follow_the_latest_branch() {
+        let (mut history, mut text) = branched();
+        assert_eq!(text.to_string(), "abd");
+        assert_eq!(history.undo(&mut text), Some((0, 2)));
+        assert_eq!(history.undo(&mut text), Some((0, 1)));
+        assert_eq!(history.undo(&mut text), None);
+        assert_eq!(text.to_string(), "a");
+        history.redo(&mut text);
+        assert_eq!(history.redo(&mut text), Some((0, 3)));
+        assert_eq!(text.to_string(), "abd");
+        assert_eq!(history.redo(&mut text), None);
+    }
+
+    #[test]
+    fn goto_crosses_branches() {
+        let (mut history, mut text) = branched();
+        assert_eq!(history.goto(2, &mut text), Some((0, 3)));
+        assert_eq!(text.to_string(), "abc");
+        assert_eq!(history.current(), 2);
+        // Redo now follows the branch just revisited.
+        history.undo(&mut text);
+        history.redo(&mut text);
+        assert_eq!(text.to_string(), "abc");
+        history.goto(0, &mut text);
+        assert_eq!(text.to_string(), "a");
+        assert_eq!(history.goto(9, &mut text), None);
+    }
+
+    #[test]
+    fn steps_and_times_are_clamped() {
+        let (history, _) = branched();
+        assert_eq!(history.step(-1), 2);
+        assert_eq!(history.step(isize::MIN), 0);
+        assert_eq!(history.step(isize::MAX), 3);
+        assert_eq!(history.state_at(Duration::from_secs(15), true), 1);
+        assert_eq!(history.state_at(Duration::MAX, true), 0);
+        assert_eq!(history.state_at(Duration::MAX, false), 3);
+    }
+}
@@ -0,0 +1,140 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Encodes key events in Vim's key notation (`<Esc>`, `<CR>`, `<C-r>`, ...) so
+/// recorded macros can live in ordinary text registers.
+pub fn encode(events: &[KeyEvent]) -> String {
+    let mut text = String::new();
+    for event in events {
+        let ctrl = event.modifiers.contains(KeyModifiers::CONTROL);
+        match event.code {
+            KeyCode::Char(c) if ctrl => text.push_str(&format!("<C-{}>", c)),
+            KeyCode::Char('<') => text.push_str("<lt>"),
+            KeyCode::Char(c) => text.push(c),
+            code => {
+                if let Some(name) = key_name(code) {
+                    text.push('<');
+                    text.push_str(name);
+                    text.push('>');
+                }
+            }
+        }
+    }
+    text
+}
+
+/// Decodes text written in Vim's key notation back into key events. Unknown
+/// `<...>` sequences are taken literally.
+pub fn decode(text: &str) -> Vec<KeyEvent> {
+    let mut events = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<'
+            && let Some(end) = rest.find('>')
+            && let Some(event) = parse_special(&rest[1..end])
+        {
+            events.push(event);
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let code = if c == '\n' {
+            KeyCode::Enter
+        } else {
+            KeyCode::Char(c)
+        };
+        events.push(KeyEvent::new(code, KeyModifiers::NONE));
+        rest = &rest[c.len_utf8()..];
+    }
+    events
+}
+
+fn key_name(code: KeyCode) -> Option<&'static str> {
+    let name = match code {
+        KeyCode::Esc => "Esc",
+        KeyCode::Enter => "CR",
+        KeyCode::Backspace => "BS",
+        KeyCode::Tab => "Tab",
+        KeyCode::Delete => "Del",
+        KeyCode::Left => "Left",
+        KeyCode::Right => "Right",
+        KeyCode::Up => "Up",
+        KeyCode::Down => "Down",
+        KeyCode::Home => "Home",
+        KeyCode::End => "End",
+        KeyCode::PageUp => "PageUp",
+        KeyCode::PageDown => "PageDown",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn parse_special(name: &str) -> Option<KeyEvent> {
+    if let Some(key) = name.strip_prefix("C-") {
+        let mut chars = key.chars();
+        let c = chars.next()?;
+        if chars.next().is_some() {
+            return None;
+        }
+        return Some(KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL));
+    }
+    let code = match name {
+        "lt" => KeyCode::Char('<'),
+        "Esc" => KeyCode::Esc,
+        "CR" => KeyCode::Enter,
+        "BS" => KeyCode::Backspace,
+        "Tab" => KeyCode::Tab,
+        "Del" => KeyCode::Delete,
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "PageUp" => KeyCode::PageUp,
+        "PageDown" => KeyCode::PageDown,
+        _ => return None,
+    };
+    Some(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn round_trip() {
+        let events = vec![
+            key(KeyCode::Char('i')),
+            key(KeyCode::Char('<')),
+            key(KeyCode::Char('é')),
+            key(KeyCode::Enter),
+            key(KeyCode::Esc),
+            KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL),
+            key(KeyCode::PageDown),
+            key(KeyCode::Char('>')),
+        ];
+        let text = encode(&events);
+        assert_eq!(text, "i<lt>é<CR><Esc><C-r><PageDown>>");
+        assert_eq!(decode(&text), events);
+    }
+
+    #[test]
+    fn unknown_names_are_literal() {
+        assert_eq!(
+            decode("<foo>\n"),
+            "<foo>"
+                .chars()
+                .map(|c| key(KeyCode::Char(c)))
+                .chain([key(KeyCode::Enter)])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(decode("<C-ab>").len(), 6);
+        assert_eq!(
+            decode("a<"),
+            vec![key(KeyCode::Char('a')), key(KeyCode::Char('<'))]
+        );
+    }
+}
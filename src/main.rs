@@ -1,25 +1,46 @@
 mod buffer;
+mod change;
 mod config;
+mod display;
 mod editor;
+mod encoding;
+mod ex;
+mod fileio;
+mod follow;
+mod hex;
+mod history;
+mod keys;
 mod mode;
+mod motion;
+mod register;
 mod renderer;
+mod screen;
 mod scripting;
+mod search;
+mod swapfile;
+mod textobject;
+mod undofile;
+mod watcher;
+mod window;
 
 use crate::config::Config;
 use crate::editor::Editor;
 use crate::renderer::Renderer;
 use crate::scripting::ScriptEngine;
+use crate::watcher::FileWatcher;
 use crossterm::{
     cursor,
     event::{self, Event},
     execute, terminal,
 };
+use std::cell::Cell;
 use std::io::stdout;
+use std::rc::Rc;
 use std::time::Duration;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let _config = Config::load();
+    let config = Config::load();
     let script_engine = ScriptEngine::new();
     script_engine.init()?;
 
@@ -28,27 +49,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut stdout = stdout();
     execute!(stdout, terminal::EnterAlternateScreen, cursor::Show)?;
 
-    let mut editor = Editor::new();
+    let mut editor = Editor::new(config);
+    editor.watcher = FileWatcher::new().ok();
+    // Progress is drawn outside the renderer, which then repaints everything.
+    let progress_shown = Rc::new(Cell::new(false));
+    editor.progress = Some(Box::new({
+        let shown = progress_shown.clone();
+        move |message| {
+            Renderer::render_progress(message);
+            shown.set(true);
+        }
+    }));
 
-    // Handle CLI arguments
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() > 1 {
-        editor.open_file(&args[1]);
+    // Handle CLI arguments: `rune [-f] [-R] [--hex] [file]`
+    let mut follow = false;
+    let mut view = false;
+    let mut hex = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-f" => follow = true,
+            "-R" => view = true,
+            "--hex" => hex = true,
+            _ => path = Some(arg),
+        }
     }
 
     let (width, height) = terminal::size()?;
     editor.terminal_size = (width, height);
 
+    if let Some(path) = path
+        && let Err(e) = editor.open_file(&path)
+    {
+        editor.status_message = e.to_string();
+    }
+    editor.buffer.readonly |= view;
+    if hex {
+        editor.toggle_hex();
+    }
+    if follow && editor.buffer.path.is_some() {
+        editor.start_follow();
+    }
+
+    let mut renderer = Renderer::new();
+    let mut redraw = true;
     loop {
-        Renderer::render(&editor)?;
+        if progress_shown.take() {
+            renderer.invalidate();
+        }
+        if redraw {
+            renderer.render(&editor)?;
+            redraw = false;
+        }
 
         if event::poll(Duration::from_millis(50))? {
             match event::read()? {
                 Event::Key(key_event) => {
                     editor.handle_key_event(key_event);
+                    redraw = true;
                 }
                 Event::Resize(w, h) => {
                     editor.terminal_size = (w, h);
+                    redraw = true;
                 }
                 _ => {}
             }
@@ -57,7 +119,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         if editor.should_quit {
             break;
         }
+        redraw |= editor.poll_file_events();
+        editor.write_swap_files();
     }
+    editor.remove_swap_files();
 
     // Restore terminal
     execute!(stdout, terminal::LeaveAlternateScreen, cursor::Show)?;
@@ -1,14 +1,16 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum Mode {
+    #[default]
     Normal,
     Insert,
-    #[allow(dead_code)]
     Visual,
+    VisualLine,
+    VisualBlock,
     Command,
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Mode::Normal
+impl Mode {
+    pub fn is_visual(self) -> bool {
+        matches!(self, Mode::Visual | Mode::VisualLine | Mode::VisualBlock)
     }
 }
@@ -0,0 +1,42 @@
+use crossterm::event::KeyCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Down,
+    Up,
+    Right,
+    WordForward,
+    WordBackward,
+    LineStart,
+    LineEnd,
+    FileStart,
+    FileEnd,
+}
+
+impl Motion {
+    /// Maps a single key to its motion. `gg` is a two-key sequence and is
+    /// resolved by the editor's prefix handling instead.
+    pub fn from_key(code: KeyCode) -> Option<Self> {
+        match code {
+            KeyCode::Char('h') | KeyCode::Left => Some(Motion::Left),
+            KeyCode::Char('j') | KeyCode::Down => Some(Motion::Down),
+            KeyCode::Char('k') | KeyCode::Up => Some(Motion::Up),
+            KeyCode::Char('l') | KeyCode::Right => Some(Motion::Right),
+            KeyCode::Char('w') => Some(Motion::WordForward),
+            KeyCode::Char('b') => Some(Motion::WordBackward),
+            KeyCode::Char('0') => Some(Motion::LineStart),
+            KeyCode::Char('$') => Some(Motion::LineEnd),
+            KeyCode::Char('G') => Some(Motion::FileEnd),
+            _ => None,
+        }
+    }
+
+    /// Linewise motions make operators act on whole lines (`dj`, `yG`).
+    pub fn is_linewise(self) -> bool {
+        matches!(
+            self,
+            Motion::Down | Motion::Up | Motion::FileStart | Motion::FileEnd
+        )
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RegisterKind {
+    #[default]
+    Charwise,
+    Linewise,
+    Blockwise,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(text: String, kind: RegisterKind) -> Self {
+        Self { text, kind }
+    }
+}
+
+/// Vim-style register file: the unnamed register `""`, named `"a`-`"z`, the
+/// yank register `"0`, the delete ring `"1`-`"9`, the small-delete register
+/// `"-`, the black hole `"_` and the read-only `".`, `":` and `"/`.
+///
+/// `"%` depends on the current file and is resolved by the editor.
+#[derive(Debug, Default)]
+pub struct Registers {
+    unnamed: Register,
+    named: HashMap<char, Register>,
+    yank: Register,
+    deletes: VecDeque<Register>,
+    small_delete: Register,
+    pub last_inserted: String,
+    pub last_command: String,
+    pub last_search: String,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `name` can follow `"` to select a register.
+    pub fn is_valid_name(name: char) -> bool {
+        name.is_ascii_alphanumeric() || matches!(name, '"' | '-' | '_' | '.' | ':' | '/' | '%')
+    }
+
+    /// Whether yanked or deleted text can go to register `name`; `".`, `":`,
+    /// `"/` and `"%` are read-only.
+    pub fn is_writable(name: char) -> bool {
+        !matches!(name, '.' | ':' | '/' | '%')
+    }
+
+    pub fn get(&self, name: char) -> Option<Register> {
+        let reg = match name {
+            '"' => self.unnamed.clone(),
+            '0' => self.yank.clone(),
+            '1'..='9' => {
+                let idx = name as usize - '1' as usize;
+                self.deletes.get(idx)?.clone()
+            }
+            '-' => self.small_delete.clone(),
+            '.' => Register::new(self.last_inserted.clone(), RegisterKind::Charwise),
+            ':' => Register::new(self.last_command.clone(), RegisterKind::Charwise),
+            '/' => Register::new(self.last_search.clone(), RegisterKind::Charwise),
+            'a'..='z' | 'A'..='Z' => self.named.get(&name.to_ascii_lowercase())?.clone(),
+            _ => return None,
+        };
+        Some(reg)
+    }
+
+    /// Records yanked text. Without a register name it also becomes `"0`.
+    pub fn yank(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            None | Some('"') => {
+                self.yank = reg.clone();
+                self.unnamed = reg;
+            }
+            Some(name) => self.write(name, reg),
+        }
+    }
+
+    /// Records deleted text. Without a register name, multi-line deletes shift
+    /// the `"1`-`"9` ring and smaller ones go to `"-`.
+    pub fn delete(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            None | Some('"') => {
+                if reg.kind == RegisterKind::Linewise || reg.text.contains('\n') {
+                    self.deletes.push_front(reg.clone());
+                    self.deletes.truncate(9);
+                } else {
+                    self.small_delete = reg.clone();
+                }
+                self.unnamed = reg;
+            }
+            Some(name) => self.write(name, reg),
+        }
+    }
+
+    /// Writes the register named for a yank or delete, as Vim does: the text
+    /// goes only there (and to `""`), without shifting the delete ring.
+    /// Read-only names are rejected by the editor beforehand.
+    fn write(&mut self, name: char, reg: Register) {
+        match name {
+            '_' => {}
+            'a'..='z' | 'A'..='Z' => self.write_named(name, reg),
+            '0' => {
+                self.yank = reg.clone();
+                self.unnamed = reg;
+            }
+            '1'..='9' => {
+                let idx = name as usize - '1' as usize;
+                if self.deletes.len() <= idx {
+                    self.deletes.resize(idx + 1, Register::default());
+                }
+                self.deletes[idx] = reg.clone();
+                self.unnamed = reg;
+            }
+            '-' => {
+                self.small_delete = reg.clone();
+                self.unnamed = reg;
+            }
+            _ => {}
+        }
+    }
+
+    /// Stores a recorded macro in a named register without touching the
+    /// unnamed register; an uppercase name appends to it.
+    pub fn record(&mut self, name: char, text: String) {
+        let key = name.to_ascii_lowercase();
+        let text = match self.named.get(&key) {
+            Some(existing) if name.is_ascii_uppercase() => existing.text.clone() + &text,
+            _ => text,
+        };
+        self.named
+            .insert(key, Register::new(text, RegisterKind::Charwise));
+    }
+
+    /// Writes a named register; an uppercase name appends to it.
+    fn write_named(&mut self, name: char, reg: Register) {
+        let key = name.to_ascii_lowercase();
+        let stored = match self.named.get(&key) {
+            Some(existing) if name.is_ascii_uppercase() => {
+                let mut text = existing.text.clone();
+                let kind = if reg.kind == RegisterKind::Linewise {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    RegisterKind::Linewise
+                } else {
+                    existing.kind
+                };
+                text.push_str(&reg.text);
+                Register::new(text, kind)
+            }
+            _ => reg,
+        };
+        self.unnamed = stored.clone();
+        self.named.insert(key, stored);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(registers: &Registers, name: char) -> Option<String> {
+        registers.get(name).map(|reg| reg.text)
+    }
+
+    fn chars(text: &str) -> Register {
+        Register::new(text.to_string(), RegisterKind::Charwise)
+    }
+
+    fn lines(text: &str) -> Register {
+        Register::new(text.to_string(), RegisterKind::Linewise)
+    }
+
+    #[test]
+    fn unnamed_yanks_and_deletes() {
+        let mut registers = Registers::new();
+        registers.yank(None, chars("word"));
+        registers.delete(None, lines("one\n"));
+        registers.delete(None, lines("two\n"));
+        registers.delete(None, chars("x"));
+        assert_eq!(text(&registers, '0').as_deref(), Some("word"));
+        assert_eq!(text(&registers, '1').as_deref(), Some("two\n"));
+        assert_eq!(text(&registers, '2').as_deref(), Some("one\n"));
+        assert_eq!(text(&registers, '-').as_deref(), Some("x"));
+        assert_eq!(text(&registers, '"').as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn named_registers_append_in_uppercase() {
+        let mut registers = Registers::new();
+        registers.yank(Some('a'), chars("foo"));
+        registers.yank(Some('A'), lines("bar\n"));
+        let reg = registers.get('a').unwrap();
+        assert_eq!(reg, lines("foo\nbar\n"));
+        assert_eq!(registers.get('"'), Some(reg));
+        // Named yanks leave `"0` alone.
+        assert_eq!(text(&registers, '0'), Some(String::new()));
+    }
+
+    #[test]
+    fn digit_and_small_delete_names_write_that_register() {
+        let mut registers = Registers::new();
+        registers.delete(None, lines("ring\n"));
+        registers.yank(Some('3'), chars("three"));
+        registers.delete(Some('0'), chars("zero"));
+        registers.yank(Some('-'), chars("dash"));
+        assert_eq!(text(&registers, '1').as_deref(), Some("ring\n"));
+        assert_eq!(text(&registers, '2').as_deref(), Some(""));
+        assert_eq!(text(&registers, '3').as_deref(), Some("three"));
+        assert_eq!(text(&registers, '0').as_deref(), Some("zero"));
+        assert_eq!(text(&registers, '-').as_deref(), Some("dash"));
+        assert_eq!(text(&registers, '"').as_deref(), Some("dash"));
+        registers.delete(Some('_'), chars("gone"));
+        assert_eq!(text(&registers, '"').as_deref(), Some("dash"));
+        assert!(!Registers::is_writable('.') && !Registers::is_writable('%'));
+        assert!(Registers::is_writable('1') && Registers::is_writable('_'));
+    }
+}
@@ -1,99 +1,457 @@
+use crate::buffer::Buffer;
+use crate::display;
 use crate::editor::Editor;
+use crate::encoding::{Encoding, FileFormat};
+use crate::hex::{self, ASCII_COLUMN, HexView, ROW_BYTES};
 use crate::mode::Mode;
+use crate::screen::Screen;
+use crate::window::Rect;
 use crossterm::{
-    cursor, execute, queue,
-    style::{Color, ResetColor, SetBackgroundColor, SetForegroundColor},
+    cursor, queue,
+    style::Color,
     terminal::{self, ClearType},
 };
 use std::io::{Write, stdout};
+use std::ops::Range;
 
-pub struct Renderer;
+/// Background highlight applied to a span of a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Highlight {
+    Match,
+    CurrentMatch,
+    Selection,
+}
+
+impl Highlight {
+    fn colors(self) -> (Color, Color) {
+        match self {
+            Highlight::Match => (
+                Color::Rgb {
+                    r: 120,
+                    g: 100,
+                    b: 30,
+                },
+                Color::White,
+            ),
+            Highlight::CurrentMatch => (
+                Color::Rgb {
+                    r: 230,
+                    g: 160,
+                    b: 40,
+                },
+                Color::Black,
+            ),
+            Highlight::Selection => (
+                Color::Rgb {
+                    r: 90,
+                    g: 60,
+                    b: 120,
+                },
+                Color::White,
+            ),
+        }
+    }
+}
+
+/// Draws the editor. Each frame is drawn into a `Screen` and only what
+/// changed since the last one is written to the terminal.
+pub struct Renderer {
+    /// What the terminal shows.
+    front: Screen,
+    /// Repaint the whole terminal on the next frame.
+    invalid: bool,
+}
 
 impl Renderer {
-    pub fn render(editor: &Editor) -> Result<(), Box<dyn std::error::Error>> {
-        let mut stdout = stdout();
+    pub fn new() -> Self {
+        Self {
+            front: Screen::new(0, 0),
+            invalid: true,
+        }
+    }
+
+    /// Repaints everything on the next frame, after something other than the
+    /// renderer drew on the terminal.
+    pub fn invalidate(&mut self) {
+        self.invalid = true;
+    }
+
+    pub fn render(&mut self, editor: &Editor) -> Result<(), Box<dyn std::error::Error>> {
         let (width, height) = (editor.terminal_size.0, editor.terminal_size.1);
+        let mut screen = Screen::new(width, height);
+        Self::draw(editor, &mut screen);
+
+        let mut stdout = stdout();
+        if self.invalid || self.front.size() != screen.size() {
+            queue!(stdout, terminal::Clear(ClearType::All))?;
+            self.front = Screen::new(width, height);
+            self.invalid = false;
+        }
+        screen.write_diff(&self.front, &mut stdout)?;
+        self.front = screen;
 
+        // Move cursor
+        let area = editor.current_rect();
+        let (cursor_row, cursor_col) = match &editor.buffer.hex {
+            Some(view) => (
+                (view.cursor / ROW_BYTES - view.scroll) as u16,
+                view.cursor_column() as u16,
+            ),
+            None => (
+                (editor.cursor.0 as isize - editor.scroll_offset.0 as isize) as u16,
+                (editor.cursor_vcol() as isize - editor.scroll_offset.1 as isize) as u16,
+            ),
+        };
         queue!(
             stdout,
-            terminal::Clear(ClearType::All),
-            cursor::MoveTo(0, 0)
+            cursor::MoveTo(area.x + cursor_col, area.y + cursor_row)
         )?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Draws a whole frame.
+    fn draw(editor: &Editor, screen: &mut Screen) {
+        let (width, height) = (editor.terminal_size.0, editor.terminal_size.1);
+        if editor.has_tab_line() {
+            Self::render_tab_line(editor, width, screen);
+        }
 
-        // Render buffer
-        let start_line = editor.scroll_offset.0;
-        let end_line =
-            (start_line + height.saturating_sub(1) as usize).min(editor.buffer.content.len_lines());
-
-        for (i, line_idx) in (start_line..end_line).enumerate() {
-            let line = editor.buffer.content.line(line_idx);
-            queue!(stdout, cursor::MoveTo(0, i as u16))?;
-
-            let start_char = editor.scroll_offset.1;
-            let line_len = line.len_chars();
-            if start_char < line_len {
-                let end_char = (start_char + width as usize).min(line_len);
-                let visible_part = line.slice(start_char..end_char).to_string();
-                Self::render_line_with_syntax(&visible_part, &mut stdout)?;
+        let rects = editor.window_rects();
+        for &(id, rect) in &rects {
+            Self::render_window(editor, id, rect, screen);
+            // Border to the right of side-by-side windows.
+            if rect.x + rect.width < width {
+                for y in rect.y..rect.y + rect.height {
+                    screen.move_to(rect.x + rect.width, y);
+                    screen.print("│");
+                }
             }
         }
 
         // Render Status Bar
-        queue!(
-            stdout,
-            cursor::MoveTo(0, height - 1),
-            terminal::Clear(ClearType::CurrentLine)
-        )?;
+        screen.move_to(0, height.saturating_sub(1));
 
         if editor.mode == Mode::Command {
-            execute!(stdout, SetForegroundColor(Color::Yellow))?;
-            write!(stdout, "{}", editor.command_buffer)?;
-            execute!(stdout, ResetColor)?;
+            screen.set_fg(Color::Yellow);
+            screen.print(&editor.command_buffer);
+            screen.reset_colors();
         } else {
             let (bg, fg, mode_name) = match editor.mode {
                 Mode::Normal => (Color::Blue, Color::White, " NORMAL "),
                 Mode::Insert => (Color::Green, Color::Black, " INSERT "),
+                Mode::VisualLine => (Color::Magenta, Color::White, " V-LINE "),
+                Mode::VisualBlock => (Color::Magenta, Color::White, " V-BLOCK "),
                 _ => (Color::Magenta, Color::White, " VISUAL "),
             };
 
-            execute!(stdout, SetBackgroundColor(bg), SetForegroundColor(fg))?;
-            write!(stdout, "{}", mode_name)?;
-            execute!(
-                stdout,
-                ResetColor,
-                SetBackgroundColor(Color::Rgb {
+            screen.set_bg(bg);
+            screen.set_fg(fg);
+            screen.print(mode_name);
+            screen.set_bg(Color::Rgb {
+                r: 50,
+                g: 50,
+                b: 50,
+            });
+            screen.set_fg(Color::White);
+
+            if let Some(name) = editor.recording {
+                screen.print(&format!(" recording @{} |", name));
+            }
+
+            // Multi-line messages are drawn above the status bar instead.
+            let message = if editor.status_message.contains('\n') {
+                ""
+            } else {
+                editor.status_message.as_str()
+            };
+            screen.print(&format!(
+                " {}{} | {} | {}",
+                editor.buffer.name(),
+                Self::buffer_flags(&editor.buffer),
+                Self::position(&editor.buffer, editor.cursor),
+                message
+            ));
+            screen.reset_colors();
+        }
+
+        let message_lines: Vec<&str> = editor.status_message.lines().collect();
+        if message_lines.len() > 1 {
+            let top = height
+                .saturating_sub(1)
+                .saturating_sub(message_lines.len() as u16);
+            for (i, line) in message_lines.iter().enumerate() {
+                screen.clear_row(top + i as u16);
+                screen.move_to(0, top + i as u16);
+                screen.print(line);
+            }
+        }
+    }
+
+    /// Draws window `id` into `rect`: its buffer text and, when the screen is
+    /// split, a status line along its bottom edge.
+    fn render_window(editor: &Editor, id: usize, rect: Rect, screen: &mut Screen) {
+        let active = id == editor.current_window;
+        let (buffer, cursor, scroll_offset) = match editor.window(id) {
+            Some(window) if !active => match editor.buffer_by_id(window.buffer_id) {
+                Some(buffer) => (buffer, window.cursor, window.scroll_offset),
+                None => return,
+            },
+            _ => (&editor.buffer, editor.cursor, editor.scroll_offset),
+        };
+        let status_rows = editor.has_splits() as u16;
+        let text_height = rect.height.saturating_sub(status_rows);
+
+        if let Some(view) = &buffer.hex {
+            Self::render_hex(view, rect, text_height, screen);
+        } else {
+            let start_line = scroll_offset.0;
+            let end_line = (start_line + text_height as usize).min(buffer.content.len_lines());
+            for (i, line_idx) in (start_line..end_line).enumerate() {
+                screen.move_to(rect.x, rect.y + i as u16);
+
+                let (visible_part, cols) = display::visible(
+                    &buffer.line_text(line_idx),
+                    scroll_offset.1,
+                    rect.width as usize,
+                    editor.config.tab_size,
+                );
+                if !visible_part.is_empty() {
+                    // Later spans take precedence, so the selection is pushed last.
+                    let mut spans: Vec<(Range<usize>, Highlight)> = editor
+                        .search_highlights(buffer, line_idx)
+                        .into_iter()
+                        .map(|m| {
+                            let current = active && line_idx == cursor.0 && m.contains(&cursor.1);
+                            let kind = if current {
+                                Highlight::CurrentMatch
+                            } else {
+                                Highlight::Match
+                            };
+                            (m, kind)
+                        })
+                        .collect();
+                    if active && let Some((sel_start, sel_end)) = editor.visual_cols(line_idx) {
+                        spans.push((sel_start..sel_end, Highlight::Selection));
+                    }
+                    // Keyword colouring is skipped for large files.
+                    let syntax = !buffer.large;
+                    if spans.is_empty() {
+                        Self::render_plain(&visible_part, syntax, screen);
+                    } else {
+                        Self::render_line_highlighted(&visible_part, &cols, &spans, syntax, screen);
+                    }
+                }
+            }
+        }
+
+        if status_rows > 0 {
+            let bg = if active {
+                Color::Rgb {
+                    r: 90,
+                    g: 90,
+                    b: 110,
+                }
+            } else {
+                Color::Rgb {
                     r: 50,
                     g: 50,
-                    b: 50
-                }),
-                SetForegroundColor(Color::White)
-            )?;
-
-            let file_name = editor.file_path.as_deref().unwrap_or("[No Name]");
-            write!(
-                stdout,
-                " {} | L:{}, C:{} | {}",
-                file_name,
-                editor.cursor.0 + 1,
-                editor.cursor.1 + 1,
-                editor.status_message
-            )?;
-            execute!(stdout, ResetColor)?;
+                    b: 50,
+                }
+            };
+            let status = format!(
+                " {}{} | {}",
+                buffer.name(),
+                Self::buffer_flags(buffer),
+                Self::position(buffer, cursor)
+            );
+            let status: String = status.chars().take(rect.width as usize).collect();
+            screen.move_to(rect.x, rect.y + text_height);
+            screen.set_bg(bg);
+            screen.set_fg(Color::White);
+            screen.print(&format!("{:<1$}", status, rect.width as usize));
+            screen.reset_colors();
         }
+    }
 
-        // Move cursor
-        let cursor_row = (editor.cursor.0 as isize - editor.scroll_offset.0 as isize) as u16;
-        let cursor_col = (editor.cursor.1 as isize - editor.scroll_offset.1 as isize) as u16;
-        queue!(stdout, cursor::MoveTo(cursor_col, cursor_row))?;
-        stdout.flush()?;
+    /// Markers shown after a buffer's name in status lines.
+    fn buffer_flags(buffer: &Buffer) -> String {
+        let mut flags = String::new();
+        if buffer.modified() {
+            flags.push_str(" [+]");
+        }
+        if !buffer.modifiable {
+            flags.push_str(" [-]");
+        } else if buffer.readonly {
+            flags.push_str(" [RO]");
+        }
+        if buffer.fileformat != FileFormat::Unix {
+            flags.push_str(&format!(" [{}]", buffer.fileformat.name()));
+        }
+        if buffer.encoding != Encoding::Utf8 {
+            flags.push_str(&format!(" [{}]", buffer.encoding.name()));
+        }
+        if buffer.follow.is_some() {
+            flags.push_str(" [follow]");
+        }
+        if buffer.hex.is_some() {
+            flags.push_str(" [hex]");
+        }
+        flags
+    }
 
-        Ok(())
+    /// Cursor position for status lines: line and column, or the byte offset
+    /// in hex mode.
+    fn position(buffer: &Buffer, cursor: (usize, usize)) -> String {
+        match &buffer.hex {
+            Some(view) => format!("0x{:08x}", view.cursor),
+            None => format!("L:{}, C:{}", cursor.0 + 1, cursor.1 + 1),
+        }
+    }
+
+    /// Draws the rows of hex mode, highlighting the byte under the cursor in
+    /// the column the cursor is not in.
+    fn render_hex(view: &HexView, rect: Rect, height: u16, screen: &mut Screen) {
+        let cursor_row = view.cursor / ROW_BYTES;
+        for i in 0..height as usize {
+            let row = view.scroll + i;
+            if row >= view.rows() {
+                break;
+            }
+            // Rows are plain ASCII, so byte and screen columns agree.
+            let line = hex::format_row(&view.bytes, row);
+            let line = &line[..line.len().min(rect.width as usize)];
+            screen.move_to(rect.x, rect.y + i as u16);
+            let col = view.cursor % ROW_BYTES;
+            let span = (row == cursor_row && !view.bytes.is_empty())
+                .then(|| {
+                    if view.ascii {
+                        let start = 10 + col * 3 + (col >= ROW_BYTES / 2) as usize;
+                        start..start + 2
+                    } else {
+                        ASCII_COLUMN + col..ASCII_COLUMN + col + 1
+                    }
+                })
+                .filter(|span| span.end <= line.len());
+            match span {
+                Some(span) => {
+                    screen.print(&line[..span.start]);
+                    let (bg, fg) = Highlight::Selection.colors();
+                    screen.set_bg(bg);
+                    screen.set_fg(fg);
+                    screen.print(&line[span.clone()]);
+                    screen.reset_colors();
+                    screen.print(&line[span.end..]);
+                }
+                None => screen.print(line),
+            }
+        }
+    }
+
+    /// Draws the tab line along the top of the screen: one label per tab page
+    /// with the name of its active buffer and `[+]` when that is modified.
+    fn render_tab_line(editor: &Editor, width: u16, screen: &mut Screen) {
+        screen.move_to(0, 0);
+        screen.set_bg(Color::Rgb {
+            r: 50,
+            g: 50,
+            b: 50,
+        });
+        screen.set_fg(Color::White);
+        let mut used = 0;
+        for (i, buffer) in editor.tab_buffers().into_iter().enumerate() {
+            let label = format!(
+                " {} {}{} ",
+                i + 1,
+                buffer.name(),
+                if buffer.modified() { " [+]" } else { "" }
+            );
+            let label: String = label.chars().take(width as usize - used).collect();
+            used += label.chars().count();
+            if i == editor.current_tab {
+                screen.set_bg(Color::Blue);
+                screen.print(&label);
+                screen.set_bg(Color::Rgb {
+                    r: 50,
+                    g: 50,
+                    b: 50,
+                });
+            } else {
+                screen.print(&label);
+            }
+        }
+        screen.print(&format!("{:<1$}", "", width as usize - used));
+        screen.reset_colors();
     }
 
-    fn render_line_with_syntax(
+    /// Renders the visible part of a line, whose chars come from line columns
+    /// `cols`, with `spans` (in line columns) highlighted and, with `syntax`,
+    /// syntax colouring on the rest.
+    fn render_line_highlighted(
         line: &str,
-        stdout: &mut std::io::Stdout,
-    ) -> Result<(), std::io::Error> {
+        cols: &[usize],
+        spans: &[(Range<usize>, Highlight)],
+        syntax: bool,
+        screen: &mut Screen,
+    ) {
+        let highlight_at = |col: usize| {
+            spans
+                .iter()
+                .rev()
+                .find(|(range, _)| range.contains(&col))
+                .map(|&(_, kind)| kind)
+        };
+        // Byte offset where each run of equally highlighted chars starts.
+        let mut runs: Vec<(usize, Option<Highlight>)> = Vec::new();
+        for (&col, (byte, _)) in cols.iter().zip(line.char_indices()) {
+            let kind = highlight_at(col);
+            if runs.last().is_none_or(|&(_, last)| last != kind) {
+                runs.push((byte, kind));
+            }
+        }
+
+        for (i, &(start, kind)) in runs.iter().enumerate() {
+            let end = runs.get(i + 1).map_or(line.len(), |&(byte, _)| byte);
+            let text = &line[start..end];
+            match kind {
+                Some(kind) => {
+                    let (bg, fg) = kind.colors();
+                    screen.set_bg(bg);
+                    screen.set_fg(fg);
+                    screen.print(text);
+                    screen.reset_colors();
+                }
+                None => Self::render_plain(text, syntax, screen),
+            }
+        }
+    }
+
+    fn render_plain(text: &str, syntax: bool, screen: &mut Screen) {
+        if syntax {
+            Self::render_line_with_syntax(text, screen);
+        } else {
+            screen.print(text);
+        }
+    }
+
+    /// Draws `message` on the bottom line straight away, for progress shown
+    /// while the editor is busy and not rendering.
+    pub fn render_progress(message: &str) {
+        let mut stdout = stdout();
+        let Ok((_, height)) = terminal::size() else {
+            return;
+        };
+        let _ = queue!(
+            stdout,
+            cursor::MoveTo(0, height.saturating_sub(1)),
+            terminal::Clear(ClearType::CurrentLine)
+        );
+        let _ = write!(stdout, "{}", message);
+        let _ = stdout.flush();
+    }
+
+    fn render_line_with_syntax(line: &str, screen: &mut Screen) {
         let keywords = [
             "fn", "let", "pub", "use", "mod", "match", "if", "else", "impl", "struct", "enum",
             "type", "trait", "return",
@@ -103,23 +461,22 @@ impl Renderer {
         for (word_pos, word) in line.match_indices(|c: char| !c.is_alphanumeric() && c != '_') {
             let prev_word = &line[last_pos..word_pos];
             if keywords.contains(&prev_word) {
-                execute!(stdout, SetForegroundColor(Color::Cyan))?;
-                write!(stdout, "{}", prev_word)?;
-                execute!(stdout, ResetColor)?;
+                screen.set_fg(Color::Cyan);
+                screen.print(prev_word);
+                screen.reset_colors();
             } else {
-                write!(stdout, "{}", prev_word)?;
+                screen.print(prev_word);
             }
-            write!(stdout, "{}", word)?;
+            screen.print(word);
             last_pos = word_pos + word.len();
         }
         let remaining = &line[last_pos..];
         if keywords.contains(&remaining) {
-            execute!(stdout, SetForegroundColor(Color::Cyan))?;
-            write!(stdout, "{}", remaining)?;
-            execute!(stdout, ResetColor)?;
+            screen.set_fg(Color::Cyan);
+            screen.print(remaining);
+            screen.reset_colors();
         } else {
-            write!(stdout, "{}", remaining)?;
+            screen.print(remaining);
         }
-        Ok(())
     }
 }
@@ -0,0 +1,273 @@
+use crate::display;
+use crossterm::{
+    cursor, queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+};
+use std::io::{self, Write};
+
+/// Colours of a cell; `None` is the terminal's default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cell {
+    /// Grapheme cluster drawn here; empty for the columns a wide one covers
+    /// after its first.
+    symbol: String,
+    style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: String::from(" "),
+            style: Style::default(),
+        }
+    }
+}
+
+/// A frame as a grid of cells. Each frame is drawn into a fresh one, and only
+/// the cells that differ from the frame before are written to the terminal.
+pub struct Screen {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    /// Where `print` writes next.
+    pen: (u16, u16),
+    style: Style,
+}
+
+impl Screen {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); width as usize * height as usize],
+            pen: (0, 0),
+            style: Style::default(),
+        }
+    }
+
+    pub fn size(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        self.pen = (x, y);
+    }
+
+    pub fn set_fg(&mut self, color: Color) {
+        self.style.fg = Some(color);
+    }
+
+    pub fn set_bg(&mut self, color: Color) {
+        self.style.bg = Some(color);
+    }
+
+    pub fn reset_colors(&mut self) {
+        self.style = Style::default();
+    }
+
+    /// Blanks row `y`.
+    pub fn clear_row(&mut self, y: u16) {
+        if y < self.height {
+            let start = y as usize * self.width as usize;
+            self.cells[start..start + self.width as usize].fill(Cell::default());
+        }
+    }
+
+    /// Writes `text` at the pen in the current colours, cut off at the right
+    /// edge of the screen.
+    pub fn print(&mut self, text: &str) {
+        for g in display::graphemes(text, 1) {
+            let (x, y) = self.pen;
+            if x >= self.width || y >= self.height {
+                return;
+            }
+            if x as usize + g.width > self.width as usize {
+                // A wide char that does not fit shows as a blank.
+                self.put(x, y, String::from(" "), 1);
+                self.pen.0 = self.width;
+                return;
+            }
+            self.put(x, y, g.shown().into_owned(), g.width);
+            self.pen.0 += g.width as u16;
+        }
+    }
+
+    fn put(&mut self, x: u16, y: u16, symbol: String, width: usize) {
+        let row = y as usize * self.width as usize;
+        let i = row + x as usize;
+        let row_end = row + self.width as usize;
+        // Blank what is left of wide chars partly overwritten.
+        if x > 0 && self.cells[i].symbol.is_empty() {
+            let mut lead = i - 1;
+            while lead > row && self.cells[lead].symbol.is_empty() {
+                lead -= 1;
+            }
+            for cell in &mut self.cells[lead..i] {
+                cell.symbol = String::from(" ");
+            }
+        }
+        for cell in self.cells[i + width..row_end]
+            .iter_mut()
+            .take_while(|cell| cell.symbol.is_empty())
+        {
+            cell.symbol = String::from(" ");
+        }
+        let style = self.style;
+        self.cells[i] = Cell { symbol, style };
+        for cell in &mut self.cells[i + 1..i + width] {
+            *cell = Cell {
+                symbol: String::new(),
+                style,
+            };
+        }
+    }
+
+    /// Writes the cells that differ from `front`, the frame on the terminal,
+    /// to `out`. The cursor is only moved where a run of changed cells starts,
+    /// and colours are only set where they change.
+    pub fn write_diff(&self, front: &Screen, out: &mut impl Write) -> io::Result<()> {
+        let width = self.width as usize;
+        let mut pen = None;
+        let mut style: Option<Style> = None;
+        for (i, cell) in self.cells.iter().enumerate() {
+            if cell.symbol.is_empty() || front.cells.get(i) == Some(cell) {
+                continue;
+            }
+            let (x, y) = ((i % width) as u16, (i / width) as u16);
+            if pen != Some((x, y)) {
+                queue!(out, cursor::MoveTo(x, y))?;
+            }
+            if style.is_none_or(|s| s.fg != cell.style.fg) {
+                queue!(
+                    out,
+                    SetForegroundColor(cell.style.fg.unwrap_or(Color::Reset))
+                )?;
+            }
+            if style.is_none_or(|s| s.bg != cell.style.bg) {
+                queue!(
+                    out,
+                    SetBackgroundColor(cell.style.bg.unwrap_or(Color::Reset))
+                )?;
+            }
+            style = Some(cell.style);
+            queue!(out, Print(&cell.symbol))?;
+            let row_end = (i / width + 1) * width;
+            let covered = self.cells[i + 1..row_end]
+                .iter()
+                .take_while(|cell| cell.symbol.is_empty())
+                .count();
+            pen = Some((x + 1 + covered as u16, y));
+        }
+        if style.is_some_and(|s| s != Style::default()) {
+            queue!(out, ResetColor)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `write_diff` writes to bring `front` up to `back`.
+    fn diff(back: &Screen, front: &Screen) -> Vec<u8> {
+        let mut out = Vec::new();
+        back.write_diff(front, &mut out).unwrap();
+        out
+    }
+
+    fn expected(f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> Vec<u8> {
+        let mut out = Vec::new();
+        f(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn identical_frames_write_nothing() {
+        let mut back = Screen::new(10, 2);
+        back.print("hello");
+        let mut front = Screen::new(10, 2);
+        front.print("hello");
+        assert!(diff(&back, &front).is_empty());
+    }
+
+    #[test]
+    fn only_changed_runs_are_written() {
+        let front = Screen::new(10, 2);
+        let mut back = Screen::new(10, 2);
+        back.move_to(2, 0);
+        back.print("ab");
+        back.move_to(5, 0);
+        back.print("c");
+        back.move_to(0, 1);
+        back.set_fg(Color::Red);
+        back.print("d");
+        let reset = SetForegroundColor(Color::Reset);
+        let no_bg = SetBackgroundColor(Color::Reset);
+        assert_eq!(
+            diff(&back, &front),
+            expected(|out| queue!(
+                out,
+                cursor::MoveTo(2, 0),
+                reset,
+                no_bg,
+                Print("a"),
+                Print("b"),
+                cursor::MoveTo(5, 0),
+                Print("c"),
+                cursor::MoveTo(0, 1),
+                SetForegroundColor(Color::Red),
+                Print("d"),
+                ResetColor
+            ))
+        );
+    }
+
+    #[test]
+    fn wide_chars_and_what_they_overwrite() {
+        let mut front = Screen::new(6, 1);
+        front.print("a漢b");
+        let mut back = Screen::new(6, 1);
+        back.print("a漢b");
+        // Writing over the second half of `漢` blanks its first half.
+        back.move_to(2, 0);
+        back.print("x");
+        let reset = SetForegroundColor(Color::Reset);
+        let no_bg = SetBackgroundColor(Color::Reset);
+        assert_eq!(
+            diff(&back, &front),
+            expected(|out| queue!(
+                out,
+                cursor::MoveTo(1, 0),
+                reset,
+                no_bg,
+                Print(" "),
+                Print("x")
+            ))
+        );
+        // The cursor skips the column a wide char covers.
+        let mut back = Screen::new(6, 1);
+        back.print("字z");
+        assert_eq!(
+            diff(&back, &Screen::new(6, 1)),
+            expected(|out| queue!(
+                out,
+                cursor::MoveTo(0, 0),
+                reset,
+                no_bg,
+                Print("字"),
+                Print("z")
+            ))
+        );
+        // A wide char that does not fit at the edge is a blank.
+        let mut back = Screen::new(3, 1);
+        back.print("ab漢");
+        assert_eq!(back.cells[2].symbol, " ");
+    }
+}
@@ -0,0 +1,412 @@
+use regex::{Regex, RegexBuilder};
+use ropey::{Rope, RopeSlice};
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Where the cursor lands relative to a match, as in `/pat/e+1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SearchOffset {
+    /// `/pat/+N`: N lines below the match, at its first column.
+    Line(isize),
+    /// `/pat/s+N` or `/pat/b+N`: N chars from the start of the match.
+    Start(isize),
+    /// `/pat/e+N`: N chars from the last char of the match.
+    End(isize),
+    #[default]
+    None,
+}
+
+impl SearchOffset {
+    fn parse(text: &str) -> Option<Self> {
+        if text.is_empty() {
+            return Some(SearchOffset::None);
+        }
+        let (kind, amount) = match text.as_bytes()[0] {
+            b's' | b'b' => ('s', &text[1..]),
+            b'e' => ('e', &text[1..]),
+            _ => ('l', text),
+        };
+        let delta = match amount {
+            "" => 0,
+            "+" => 1,
+            "-" => -1,
+            _ => amount
+                .strip_prefix('+')
+                .unwrap_or(amount)
+                .parse::<isize>()
+                .ok()?,
+        };
+        Some(match kind {
+            's' => SearchOffset::Start(delta),
+            'e' => SearchOffset::End(delta),
+            _ => SearchOffset::Line(delta),
+        })
+    }
+}
+
+/// Splits `/pat/offset` input (without the leading delimiter) into the pattern
+/// and its offset.
+pub fn parse_query(input: &str, delimiter: char) -> Option<(String, SearchOffset)> {
+    let (pattern, rest) = split_delimited(input, delimiter);
+    let offset = SearchOffset::parse(rest.unwrap_or(""))?;
+    Some((pattern, offset))
+}
+
+/// Reads text up to an unescaped `delimiter`, returning it and whatever follows
+/// the delimiter (`None` if it never appears). A delimiter escaped with `\` is
+/// taken literally; other escapes are kept for the regex engine.
+pub fn split_delimited(input: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut text = String::new();
+    let mut chars = input.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => text.push(next),
+                Some((_, next)) => {
+                    text.push('\\');
+                    text.push(next);
+                }
+                None => text.push('\\'),
+            }
+        } else if c == delimiter {
+            return (text, Some(&input[i + c.len_utf8()..]));
+        } else {
+            text.push(c);
+        }
+    }
+    (text, None)
+}
+
+/// Compiles a search pattern. `\c` / `\C` anywhere in it force case-insensitive
+/// / case-sensitive matching; otherwise `ignorecase` applies, except that with
+/// `smartcase` an uppercase letter makes the search case-sensitive.
+pub fn compile(pattern: &str, ignorecase: bool, smartcase: bool) -> Result<Regex, regex::Error> {
+    let (mut source, mut force) = (String::new(), None);
+    // Walked escape by escape, so the `c` of an escaped backslash (`\\c`)
+    // stays part of the pattern.
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            source.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('c') => force = Some(true),
+            Some('C') => force = force.or(Some(false)),
+            Some(next) => {
+                source.push(c);
+                source.push(next);
+            }
+            None => source.push(c),
+        }
+    }
+    let ignore =
+        force.unwrap_or(ignorecase && !(smartcase && source.chars().any(char::is_uppercase)));
+    RegexBuilder::new(&source).case_insensitive(ignore).build()
+}
+
+/// Text of line `row` without its line break, borrowed straight from the rope
+/// when the line lies within a single chunk.
+pub fn line_text(text: &Rope, row: usize) -> (RopeSlice<'_>, Cow<'_, str>) {
+    let line = text.line(row);
+    let mut len = line.len_chars();
+    while len > 0 && matches!(line.char(len - 1), '\n' | '\r') {
+        len -= 1;
+    }
+    let line = line.slice(..len);
+    let content = match line.as_str() {
+        Some(s) => Cow::Borrowed(s),
+        None => Cow::Owned(line.to_string()),
+    };
+    (line, content)
+}
+
+/// All matches on line `row` as char ranges relative to the line start.
+pub fn line_matches(text: &Rope, regex: &Regex, row: usize) -> Vec<Range<usize>> {
+    if row >= text.len_lines() {
+        return Vec::new();
+    }
+    let (line, content) = line_text(text, row);
+    regex
+        .find_iter(&content)
+        .map(|m| line.byte_to_char(m.start())..line.byte_to_char(m.end()))
+        .collect()
+}
+
+/// Finds the first match starting after char index `from` (or, backwards, the
+/// last one starting before it), wrapping around the end of the buffer.
+/// Returns the match as buffer char indices and whether the search wrapped.
+pub fn find(
+    text: &Rope,
+    regex: &Regex,
+    from: usize,
+    forward: bool,
+) -> Option<(Range<usize>, bool)> {
+    let lines = text.len_lines();
+    let from_row = text.char_to_line(from.min(text.len_chars()));
+
+    for step in 0..=lines {
+        let row = if forward {
+            (from_row + step) % lines
+        } else {
+            (from_row + lines - step % lines) % lines
+        };
+        let wrapped = if forward {
+            from_row + step >= lines
+        } else {
+            step > from_row
+        };
+        let line_start = text.line_to_char(row);
+        let mut matches = line_matches(text, regex, row)
+            .into_iter()
+            .map(|m| line_start + m.start..line_start + m.end);
+
+        let found = match (forward, step) {
+            (true, 0) => matches.find(|m| m.start > from),
+            (true, _) if step == lines => matches.find(|m| m.start <= from),
+            (true, _) => matches.next(),
+            (false, 0) => matches.rev().find(|m| m.start < from),
+            (false, _) if step == lines => matches.rev().find(|m| m.start >= from),
+            (false, _) => matches.next_back(),
+        };
+        if let Some(m) = found {
+            return Some((m, wrapped));
+        }
+    }
+    None
+}
+
+/// Bytes of text `find_in_blocks` hands the regex at a time.
+const BLOCK_BYTES: usize = 256 * 1024;
+
+/// Same as `find`, but takes the text a block of whole lines at a time rather
+/// than copying each line out of the rope, which keeps searching large files
+/// fast. The regex still runs on each line of a block on its own.
+pub fn find_in_blocks(
+    text: &Rope,
+    regex: &Regex,
+    from: usize,
+    forward: bool,
+) -> Option<(Range<usize>, bool)> {
+    let from = from.min(text.len_chars());
+    let blocks = text.len_bytes().div_ceil(BLOCK_BYTES).max(1);
+    // Char index where block `i` starts: the start of the line holding its
+    // first byte.
+    let boundary = |i: usize| {
+        if i >= blocks {
+            text.len_chars()
+        } else {
+            text.line_to_char(text.byte_to_line(i * BLOCK_BYTES))
+        }
+    };
+    let mut from_block = (text.char_to_byte(from) / BLOCK_BYTES).min(blocks - 1);
+    while from_block > 0 && boundary(from_block) > from {
+        from_block -= 1;
+    }
+    while from_block + 1 < blocks && boundary(from_block + 1) <= from {
+        from_block += 1;
+    }
+
+    for step in 0..=blocks {
+        let block = if forward {
+            (from_block + step) % blocks
+        } else {
+            (from_block + blocks - step % blocks) % blocks
+        };
+        let wrapped = if forward {
+            from_block + step >= blocks
+        } else {
+            step > from_block
+        };
+        let (start, end) = (boundary(block), boundary(block + 1));
+        let slice = text.slice(start..end);
+        let content = match slice.as_str() {
+            Some(s) => Cow::Borrowed(s),
+            None => Cow::Owned(slice.to_string()),
+        };
+        let mut found: Vec<Range<usize>> = Vec::new();
+        let mut line_start = start;
+        let mut lines = content.split('\n').peekable();
+        while let Some(line) = lines.next() {
+            // The empty piece after a block's last line break is only a line
+            // of its own at the end of the text.
+            if lines.peek().is_none() && line.is_empty() && block + 1 < blocks {
+                break;
+            }
+            // Char indices are counted along the line rather than looked up
+            // in the rope for every match.
+            let line_text = line.trim_end_matches('\r');
+            let (mut byte, mut idx) = (0, line_start);
+            for m in regex.find_iter(line_text) {
+                idx += line_text[byte..m.start()].chars().count();
+                let match_start = idx;
+                idx += m.as_str().chars().count();
+                byte = m.end();
+                found.push(match_start..idx);
+            }
+            line_start += line.chars().count() + 1;
+        }
+        let mut matches = found.into_iter();
+
+        let found = match (forward, step) {
+            (true, 0) => matches.find(|m| m.start > from),
+            (true, _) if step == blocks => matches.find(|m| m.start <= from),
+            (true, _) => matches.next(),
+            (false, 0) => matches.rev().find(|m| m.start < from),
+            (false, _) if step == blocks => matches.rev().find(|m| m.start >= from),
+            (false, _) => matches.next_back(),
+        };
+        if let Some(m) = found {
+            return Some((m, wrapped));
+        }
+    }
+    None
+}
+
+/// Char index the cursor moves to for match `m` under `offset`.
+pub fn apply_offset(text: &Rope, m: &Range<usize>, offset: SearchOffset) -> usize {
+    let len = text.len_chars();
+    let clamp = |idx: isize| idx.clamp(0, len.saturating_sub(1) as isize) as usize;
+    match offset {
+        SearchOffset::None => m.start,
+        SearchOffset::Start(delta) => clamp(m.start as isize + delta),
+        SearchOffset::End(delta) => clamp(m.end.max(m.start + 1) as isize - 1 + delta),
+        SearchOffset::Line(delta) => {
+            let row = text.char_to_line(m.start) as isize + delta;
+            let row = row.clamp(0, text.len_lines().saturating_sub(1) as isize) as usize;
+            text.line_to_char(row)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Enough lines for several blocks, with blank lines, trailing blanks,
+    /// commas and CRs left over from mixed line endings.
+    fn sample() -> Rope {
+        let mut text = String::new();
+        for i in 0..60_000 {
+            match i % 5 {
+                0 => text.push_str("foo  \n"),
+                1 => text.push('\n'),
+                2 => text.push_str(&format!("a,b, c{}\r\n", i)),
+                3 => text.push_str("bar\tbaz  qux\n"),
+                _ => text.push_str(&format!("line {} x\n", i)),
+            }
+        }
+        text.push_str("last");
+        Rope::from_str(&text)
+    }
+
+    #[test]
+    fn find_in_blocks_matches_find() {
+        let text = sample();
+        assert!(text.len_bytes() > 2 * BLOCK_BYTES);
+        let len = text.len_chars();
+        let froms = [0, BLOCK_BYTES - 3, BLOCK_BYTES + 11, len];
+        for pattern in [r"\s+$", "[^,]+", r"\S+\s+", "^$", "^", "foo", "x+"] {
+            let regex = compile(pattern, false, false).unwrap();
+            for &from in &froms {
+                for forward in [true, false] {
+                    assert_eq!(
+                        find_in_blocks(&text, &regex, from, forward),
+                        find(&text, &regex, from, forward),
+                        "/{}/ from {} forward {}",
+                        pattern,
+                        from,
+                        forward
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn find_in_blocks_wraps_like_find() {
+        let text = sample();
+        let from = BLOCK_BYTES + 11;
+        for pattern in ["last", "nope"] {
+            let regex = compile(pattern, false, false).unwrap();
+            for forward in [true, false] {
+                assert_eq!(
+                    find_in_blocks(&text, &regex, from, forward),
+                    find(&text, &regex, from, forward)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn trailing_blanks_before_an_empty_line() {
+        let text = Rope::from_str("foo  \n\nbar");
+        let regex = compile(r"\s+$", false, false).unwrap();
+        assert_eq!(find_in_blocks(&text, &regex, 0, true), Some((3..5, false)));
+        assert_eq!(find(&text, &regex, 0, true), Some((3..5, false)));
+    }
+
+    #[test]
+    fn case_flags() {
+        let matches = |pattern, text, ignorecase, smartcase| {
+            compile(pattern, ignorecase, smartcase)
+                .unwrap()
+                .is_match(text)
+        };
+        assert!(matches("abc", "ABC", true, false));
+        assert!(!matches("abc", "ABC", false, false));
+        assert!(!matches("Abc", "ABC", true, true));
+        assert!(matches(r"abc\c", "ABC", false, false));
+        assert!(matches(r"A\Cbc\c", "ABC", false, true));
+        assert!(!matches(r"\Cabc", "ABC", true, false));
+        // An escaped backslash followed by `c` is a backslash and a `c`.
+        assert!(matches(r"a\\c", r"a\c", false, false));
+        assert!(!matches(r"a\\c", "A", true, false));
+        assert!(matches(r"a\\\c", r"A\", false, false));
+    }
+
+    #[test]
+    fn queries_and_offsets() {
+        assert_eq!(
+            split_delimited(r"a\/b\d/e+1", '/'),
+            (String::from(r"a/b\d"), Some("e+1"))
+        );
+        assert_eq!(split_delimited("abc", '/'), (String::from("abc"), None));
+        let parse = |input| parse_query(input, '/').map(|(_, offset)| offset);
+        assert_eq!(parse("a"), Some(SearchOffset::None));
+        assert_eq!(parse("a/"), Some(SearchOffset::None));
+        assert_eq!(parse("a/+"), Some(SearchOffset::Line(1)));
+        assert_eq!(parse("a/-3"), Some(SearchOffset::Line(-3)));
+        assert_eq!(parse("a/e"), Some(SearchOffset::End(0)));
+        assert_eq!(parse("a/e-"), Some(SearchOffset::End(-1)));
+        assert_eq!(parse("a/b+2"), Some(SearchOffset::Start(2)));
+        assert_eq!(parse("a/s-1"), Some(SearchOffset::Start(-1)));
+        assert_eq!(parse("a/x"), None);
+
+        let text = Rope::from_str("one two\nthree\n");
+        let m = 4..7;
+        assert_eq!(apply_offset(&text, &m, SearchOffset::None), 4);
+        assert_eq!(apply_offset(&text, &m, SearchOffset::End(0)), 6);
+        assert_eq!(apply_offset(&text, &m, SearchOffset::End(2)), 8);
+        assert_eq!(apply_offset(&text, &m, SearchOffset::Start(-9)), 0);
+        assert_eq!(apply_offset(&text, &m, SearchOffset::Line(1)), 8);
+        assert_eq!(apply_offset(&text, &m, SearchOffset::Line(9)), 14);
+    }
+
+    #[test]
+    fn find_wraps_around() {
+        let text = Rope::from_str("ab\ncd ab\n");
+        let regex = compile("ab", false, false).unwrap();
+        assert_eq!(find(&text, &regex, 0, true), Some((6..8, false)));
+        assert_eq!(find(&text, &regex, 6, true), Some((0..2, true)));
+        assert_eq!(find(&text, &regex, 6, false), Some((0..2, false)));
+        assert_eq!(find(&text, &regex, 0, false), Some((6..8, true)));
+        // The only match is found again from itself.
+        let regex = compile("cd", false, false).unwrap();
+        assert_eq!(find(&text, &regex, 3, true), Some((3..5, true)));
+        assert_eq!(
+            line_matches(&text, &compile("[a-d]", false, false).unwrap(), 1),
+            vec![0..1, 1..2, 3..4, 4..5]
+        );
+    }
+}
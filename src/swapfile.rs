@@ -0,0 +1,90 @@
+use crate::fileio;
+use crate::watcher;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Extensions tried in turn, so a second instance editing the same file gets
+/// a swap file of its own.
+const EXTENSIONS: [&str; 3] = ["swp", "swo", "swn"];
+
+/// Swap file kept while a file is being edited: who is editing it and, once
+/// there are unsaved changes, the text to recover them from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapFile {
+    pub pid: u32,
+    pub text: Option<String>,
+}
+
+impl SwapFile {
+    /// Written by another RUNE that is still running.
+    pub fn is_live(&self) -> bool {
+        self.pid != std::process::id() && process_running(self.pid)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_running(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Without `/proc` there is no cheap check, so assume the worst.
+#[cfg(not(target_os = "linux"))]
+fn process_running(_pid: u32) -> bool {
+    true
+}
+
+/// Swap files for `path` inside `swap_dir`, named after the canonical path
+/// with `/` replaced by `%` like undo files. Only the directory is resolved,
+/// so a file not yet written has swap files too.
+fn swap_paths(swap_dir: &Path, path: &str) -> Vec<PathBuf> {
+    let Some(canonical) = watcher::watch_key(path) else {
+        return Vec::new();
+    };
+    let name = canonical.to_string_lossy().replace(['/', '\\'], "%");
+    EXTENSIONS
+        .iter()
+        .map(|ext| swap_dir.join(format!("{}.{}", name, ext)))
+        .collect()
+}
+
+/// The first swap file for `path` left by another process.
+pub fn find(swap_dir: &Path, path: &str) -> Option<(PathBuf, SwapFile)> {
+    swap_paths(swap_dir, path)
+        .into_iter()
+        .find_map(|swap_path| {
+            let swap = load(&swap_path)?;
+            (swap.pid != std::process::id()).then_some((swap_path, swap))
+        })
+}
+
+/// A swap file name for `path` that nobody is using.
+pub fn free_path(swap_dir: &Path, path: &str) -> Option<PathBuf> {
+    swap_paths(swap_dir, path)
+        .into_iter()
+        .find(|swap_path| !swap_path.exists())
+}
+
+pub fn load(swap_path: &Path) -> Option<SwapFile> {
+    let content = fs::read_to_string(swap_path).ok()?;
+    toml::from_str(&content).ok()
+}
+
+/// Writes the swap file, with `text` when the buffer has unsaved changes.
+/// It goes through a temporary file so a crash mid-write keeps the old one,
+/// and gets the permissions of `path`, the file being edited.
+pub fn save(swap_path: &Path, path: &str, text: Option<&Rope>) -> std::io::Result<()> {
+    if let Some(dir) = swap_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let swap = SwapFile {
+        pid: std::process::id(),
+        text: text.map(|t| t.to_string()),
+    };
+    let serialized = toml::to_string(&swap).map_err(std::io::Error::other)?;
+    let mut temp = swap_path.as_os_str().to_owned();
+    temp.push(".tmp");
+    fileio::write_restricted(Path::new(&temp), &serialized, path)?;
+    fs::rename(temp, swap_path)
+}
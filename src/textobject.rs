@@ -0,0 +1,516 @@
+use ropey::Rope;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObjectKind {
+    Word,
+    BigWord,
+    Sentence,
+    Paragraph,
+    Quote(char),
+    Pair(char, char),
+    Tag,
+}
+
+/// A Vim text object such as `iw` or `a(`, resolved against the buffer into a
+/// char range that operators and Visual mode consume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextObject {
+    pub kind: TextObjectKind,
+    pub around: bool,
+}
+
+impl TextObject {
+    /// Builds the object named by the key typed after `i` (`around == false`)
+    /// or `a` (`around == true`).
+    pub fn from_key(around: bool, key: char) -> Option<Self> {
+        let kind = match key {
+            'w' => TextObjectKind::Word,
+            'W' => TextObjectKind::BigWord,
+            's' => TextObjectKind::Sentence,
+            'p' => TextObjectKind::Paragraph,
+            '"' | '\'' | '`' => TextObjectKind::Quote(key),
+            '(' | ')' | 'b' => TextObjectKind::Pair('(', ')'),
+            '[' | ']' => TextObjectKind::Pair('[', ']'),
+            '{' | '}' | 'B' => TextObjectKind::Pair('{', '}'),
+            '<' | '>' => TextObjectKind::Pair('<', '>'),
+            't' => TextObjectKind::Tag,
+            _ => return None,
+        };
+        Some(Self { kind, around })
+    }
+
+    /// Paragraph objects select whole lines.
+    pub fn is_linewise(&self) -> bool {
+        self.kind == TextObjectKind::Paragraph
+    }
+
+    /// Resolves the object around char index `pos`. A `count` selects that
+    /// many consecutive words, sentences or paragraphs, or the `count`-th
+    /// enclosing bracket pair or tag.
+    pub fn range(&self, text: &Rope, pos: usize, count: usize) -> Option<Range<usize>> {
+        if pos >= text.len_chars() && self.kind != TextObjectKind::Paragraph {
+            return None;
+        }
+        match self.kind {
+            TextObjectKind::Quote(q) => quote(text, pos, q, self.around),
+            TextObjectKind::Pair(open, close) => pair(text, pos, open, close, self.around, count),
+            TextObjectKind::Tag => tag(text, pos, self.around, count),
+            _ => {
+                let mut range = self.single_range(text, pos)?;
+                for _ in 1..count {
+                    if range.end >= text.len_chars() {
+                        break;
+                    }
+                    range.end = self.single_range(text, range.end)?.end;
+                }
+                Some(range)
+            }
+        }
+    }
+
+    fn single_range(&self, text: &Rope, pos: usize) -> Option<Range<usize>> {
+        match self.kind {
+            TextObjectKind::Word => Some(word(text, pos, self.around, false)),
+            TextObjectKind::BigWord => Some(word(text, pos, self.around, true)),
+            TextObjectKind::Sentence => Some(sentence(text, pos, self.around)),
+            TextObjectKind::Paragraph => paragraph(text, pos, self.around),
+            _ => None,
+        }
+    }
+}
+
+/// Character class used to delimit words: line breaks, blanks, keyword chars
+/// and punctuation. With `big` every non-blank char is in one class.
+fn char_class(c: char, big: bool) -> u8 {
+    if c == '\n' {
+        0
+    } else if c.is_whitespace() {
+        1
+    } else if big || c.is_alphanumeric() || c == '_' {
+        2
+    } else {
+        3
+    }
+}
+
+fn is_blank(c: char) -> bool {
+    c.is_whitespace() && c != '\n'
+}
+
+/// Extends `pos` to the run of chars sharing its class, stopping at line breaks.
+fn class_run(text: &Rope, pos: usize, big: bool) -> Range<usize> {
+    let class = char_class(text.char(pos), big);
+    if class == 0 {
+        return pos..pos + 1;
+    }
+    let mut start = pos;
+    while start > 0 && char_class(text.char(start - 1), big) == class {
+        start -= 1;
+    }
+    let mut end = pos + 1;
+    while end < text.len_chars() && char_class(text.char(end), big) == class {
+        end += 1;
+    }
+    start..end
+}
+
+fn word(text: &Rope, pos: usize, around: bool, big: bool) -> Range<usize> {
+    let run = class_run(text, pos, big);
+    if !around || text.char(pos) == '\n' {
+        return run;
+    }
+    let len = text.len_chars();
+
+    if is_blank(text.char(pos)) {
+        // Blanks plus the following word.
+        if run.end < len && text.char(run.end) != '\n' {
+            return run.start..class_run(text, run.end, big).end;
+        }
+        return run;
+    }
+
+    // The word plus trailing blanks, or leading blanks when there are none.
+    if run.end < len && is_blank(text.char(run.end)) {
+        return run.start..class_run(text, run.end, big).end;
+    }
+    if run.start > 0 && is_blank(text.char(run.start - 1)) {
+        return class_run(text, run.start - 1, big).start..run.end;
+    }
+    run
+}
+
+fn is_sentence_end(c: char) -> bool {
+    matches!(c, '.' | '!' | '?')
+}
+
+fn is_paragraph_break(text: &Rope, idx: usize) -> bool {
+    idx >= 1 && text.char(idx - 1) == '\n' && (idx == 1 || text.char(idx - 2) == '\n')
+}
+
+fn sentence(text: &Rope, pos: usize, around: bool) -> Range<usize> {
+    let len = text.len_chars();
+
+    let mut start = pos;
+    while start > 0 && !is_paragraph_break(text, start) {
+        if text.char(start - 1).is_whitespace() {
+            let mut prev = start - 1;
+            while prev > 0 && text.char(prev - 1).is_whitespace() {
+                prev -= 1;
+            }
+            if prev > 0 && is_sentence_end(text.char(prev - 1)) {
+                break;
+            }
+        }
+        start -= 1;
+    }
+    while start < pos && text.char(start).is_whitespace() {
+        start += 1;
+    }
+
+    let mut end = pos;
+    while end < len {
+        let c = text.char(end);
+        if is_sentence_end(c) && (end + 1 == len || text.char(end + 1).is_whitespace()) {
+            end += 1;
+            break;
+        }
+        if c == '\n' && end + 1 < len && text.char(end + 1) == '\n' {
+            break;
+        }
+        end += 1;
+    }
+
+    if !around {
+        return start..end;
+    }
+    let mut trailing = end;
+    while trailing < len && is_blank(text.char(trailing)) {
+        trailing += 1;
+    }
+    if trailing == end && end < len && text.char(end) == '\n' && !is_paragraph_break(text, end + 1)
+    {
+        trailing += 1;
+    }
+    if trailing > end {
+        return start..trailing;
+    }
+    let mut leading = start;
+    while leading > 0 && is_blank(text.char(leading - 1)) {
+        leading -= 1;
+    }
+    leading..end
+}
+
+fn line_is_blank(text: &Rope, row: usize) -> bool {
+    text.line(row).chars().all(char::is_whitespace)
+}
+
+fn paragraph(text: &Rope, pos: usize, around: bool) -> Option<Range<usize>> {
+    let mut lines = text.len_lines();
+    // A trailing line break yields an empty final "line" that is not real text.
+    if lines > 1 && text.line(lines - 1).len_chars() == 0 {
+        lines -= 1;
+    }
+    let row = text
+        .char_to_line(pos.min(text.len_chars()))
+        .min(lines.saturating_sub(1));
+    let blank = line_is_blank(text, row);
+
+    let mut first = row;
+    while first > 0 && line_is_blank(text, first - 1) == blank {
+        first -= 1;
+    }
+    let mut last = row;
+    while last + 1 < lines && line_is_blank(text, last + 1) == blank {
+        last += 1;
+    }
+
+    if around {
+        if last + 1 < lines {
+            // Swallow the following run of the opposite kind.
+            last += 1;
+            while last + 1 < lines && line_is_blank(text, last + 1) != blank {
+                last += 1;
+            }
+        } else if !blank {
+            while first > 0 && line_is_blank(text, first - 1) {
+                first -= 1;
+            }
+        }
+    }
+
+    let start = text.line_to_char(first);
+    let end = if last + 1 < text.len_lines() {
+        text.line_to_char(last + 1)
+    } else {
+        text.len_chars()
+    };
+    Some(start..end)
+}
+
+fn quote(text: &Rope, pos: usize, q: char, around: bool) -> Option<Range<usize>> {
+    let row = text.char_to_line(pos);
+    let line_start = text.line_to_char(row);
+    let line = text.line(row);
+
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for (i, c) in line.chars().enumerate() {
+        if c == q && !escaped {
+            quotes.push(line_start + i);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+
+    // Quotes pair up left to right; take the pair containing the cursor or
+    // else the first one after it.
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|p| (p[0], p[1]))
+        .find(|&(_, close)| pos <= close)?;
+
+    if !around {
+        return Some(open + 1..close);
+    }
+    let len = text.len_chars();
+    let mut end = close + 1;
+    while end < len && is_blank(text.char(end)) {
+        end += 1;
+    }
+    let mut start = open;
+    if end == close + 1 {
+        while start > line_start && is_blank(text.char(start - 1)) {
+            start -= 1;
+        }
+    }
+    Some(start..end)
+}
+
+fn pair(
+    text: &Rope,
+    pos: usize,
+    open: char,
+    close: char,
+    around: bool,
+    count: usize,
+) -> Option<Range<usize>> {
+    let len = text.len_chars();
+
+    // Walk back to the `count`-th unmatched opening char; the cursor sitting on
+    // an opening or closing char counts as being inside that pair.
+    let mut levels = count.max(1);
+    let mut depth = 0usize;
+    let mut idx = if text.char(pos) == close {
+        pos
+    } else {
+        pos + 1
+    };
+    let open_idx = loop {
+        if idx == 0 {
+            return None;
+        }
+        idx -= 1;
+        let c = text.char(idx);
+        if c == close {
+            depth += 1;
+        } else if c == open {
+            if depth > 0 {
+                depth -= 1;
+            } else {
+                levels -= 1;
+                if levels == 0 {
+                    break idx;
+                }
+            }
+        }
+    };
+
+    let mut depth = 0usize;
+    let mut close_idx = open_idx + 1;
+    loop {
+        if close_idx >= len {
+            return None;
+        }
+        let c = text.char(close_idx);
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                break;
+            }
+            depth -= 1;
+        }
+        close_idx += 1;
+    }
+
+    if around {
+        Some(open_idx..close_idx + 1)
+    } else {
+        Some(open_idx + 1..close_idx)
+    }
+}
+
+struct Tag {
+    start: usize,
+    end: usize,
+    name: String,
+    closing: bool,
+}
+
+/// Scans the buffer for `<name ...>` and `</name>` tags, skipping
+/// self-closing tags, comments and declarations.
+fn scan_tags(text: &Rope) -> Vec<Tag> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tags = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '<' {
+            i += 1;
+            continue;
+        }
+        let Some(gt) = chars[i..].iter().position(|&c| c == '>').map(|off| i + off) else {
+            break;
+        };
+        let closing = chars.get(i + 1) == Some(&'/');
+        let name_start = if closing { i + 2 } else { i + 1 };
+        let name: String = chars[name_start..gt]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+            .collect();
+        let self_closing = chars[gt - 1] == '/';
+        if !name.is_empty() && !self_closing {
+            tags.push(Tag {
+                start: i,
+                end: gt + 1,
+                name,
+                closing,
+            });
+        }
+        i = gt + 1;
+    }
+    tags
+}
+
+fn tag(text: &Rope, pos: usize, around: bool, count: usize) -> Option<Range<usize>> {
+    let mut stack: Vec<&Tag> = Vec::new();
+    let mut containing = Vec::new();
+    let tags = scan_tags(text);
+
+    for t in &tags {
+        if !t.closing {
+            stack.push(t);
+            continue;
+        }
+        let Some(depth) = stack.iter().rposition(|open| open.name == t.name) else {
+            continue;
+        };
+        let open = stack[depth];
+        stack.truncate(depth);
+        if open.start <= pos && pos < t.end {
+            // Pairs close inner-first, so containing pairs arrive innermost first.
+            containing.push((open.start, open.end, t.start, t.end));
+        }
+    }
+
+    let (outer_start, inner_start, inner_end, outer_end) = *containing.get(count.max(1) - 1)?;
+    if around {
+        Some(outer_start..outer_end)
+    } else {
+        Some(inner_start..inner_end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text `key` (after `i`, or `a` when `around`) selects with the
+    /// cursor on the first `|`, which is removed from `text` first.
+    fn select(text: &str, around: bool, key: char, count: usize) -> Option<String> {
+        let pos = text.find('|').unwrap();
+        let text = Rope::from_str(&text.replacen('|', "", 1));
+        let pos = text.byte_to_char(pos);
+        let range = TextObject::from_key(around, key)?.range(&text, pos, count)?;
+        Some(text.slice(range).to_string())
+    }
+
+    fn inner(text: &str, key: char) -> Option<String> {
+        select(text, false, key, 1)
+    }
+
+    fn around(text: &str, key: char) -> Option<String> {
+        select(text, true, key, 1)
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(inner("foo b|ar baz", 'w').as_deref(), Some("bar"));
+        assert_eq!(around("foo b|ar baz", 'w').as_deref(), Some("bar "));
+        // Without blanks after it, `aw` takes those before.
+        assert_eq!(around("foo b|ar", 'w').as_deref(), Some(" bar"));
+        assert_eq!(inner("a.b|c.d", 'w').as_deref(), Some("bc"));
+        assert_eq!(inner("a.b|c.d e", 'W').as_deref(), Some("a.bc.d"));
+        assert_eq!(inner("foo |  bar", 'w').as_deref(), Some("   "));
+        assert_eq!(
+            select("|one two three four", false, 'w', 3).as_deref(),
+            Some("one two")
+        );
+        assert_eq!(
+            select("|one two three four", true, 'w', 2).as_deref(),
+            Some("one two ")
+        );
+    }
+
+    #[test]
+    fn sentences_and_paragraphs() {
+        let text = "One. Tw|o here.  Three.";
+        assert_eq!(inner(text, 's').as_deref(), Some("Two here."));
+        assert_eq!(around(text, 's').as_deref(), Some("Two here.  "));
+        let text = "a\nb|\n\nc\n";
+        assert_eq!(inner(text, 'p').as_deref(), Some("a\nb\n"));
+        assert_eq!(around(text, 'p').as_deref(), Some("a\nb\n\n"));
+        assert!(TextObject::from_key(false, 'p').unwrap().is_linewise());
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            inner(r#"say "hi |there" now"#, '"').as_deref(),
+            Some("hi there")
+        );
+        assert_eq!(
+            around(r#"say "hi |there" now"#, '"').as_deref(),
+            Some("\"hi there\" ")
+        );
+        // Before the first quote on the line, the first quoted string counts.
+        assert_eq!(inner(r#"s|ay 'a' 'b'"#, '\'').as_deref(), Some("a"));
+        assert_eq!(inner(r#"say "e\"|x""#, '"').as_deref(), Some(r#"e\"x"#));
+        assert_eq!(inner("no |quotes", '"'), None);
+    }
+
+    #[test]
+    fn pairs_and_counts() {
+        let text = "f(a, (b|, c), d)";
+        assert_eq!(inner(text, '(').as_deref(), Some("b, c"));
+        assert_eq!(around(text, 'b').as_deref(), Some("(b, c)"));
+        assert_eq!(select(text, false, ')', 2).as_deref(), Some("a, (b, c), d"));
+        assert_eq!(select(text, false, '(', 3), None);
+        assert_eq!(inner("x[|1]", ']').as_deref(), Some("1"));
+        assert_eq!(inner("{\n  |a\n}", 'B').as_deref(), Some("\n  a\n"));
+        assert_eq!(inner("|(a)", '(').as_deref(), Some("a"));
+        assert_eq!(inner("a |b", '('), None);
+    }
+
+    #[test]
+    fn tags() {
+        let text = "<div><p>he|llo</p><br/></div>";
+        assert_eq!(inner(text, 't').as_deref(), Some("hello"));
+        assert_eq!(around(text, 't').as_deref(), Some("<p>hello</p>"));
+        assert_eq!(
+            select(text, false, 't', 2).as_deref(),
+            Some("<p>hello</p><br/>")
+        );
+        assert_eq!(inner("<a>|x", 't'), None);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::fileio;
+use crate::history::History;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// On-disk undo history for one file, valid only for text with `hash`.
+#[derive(Serialize, Deserialize)]
+struct UndoFile {
+    hash: String,
+    history: History,
+}
+
+/// FNV-1a hash of the text; unlike `DefaultHasher` it is stable across runs
+/// and Rust releases.
+pub fn content_hash(text: &Rope) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for chunk in text.chunks() {
+        for byte in chunk.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Undo file for `path` inside `undo_dir`, named after the canonical path with
+/// `/` replaced by `%` as Vim does.
+pub fn undo_path(undo_dir: &Path, path: &str) -> Option<PathBuf> {
+    let canonical = fs::canonicalize(path).ok()?;
+    let name = canonical.to_string_lossy().replace(['/', '\\'], "%");
+    Some(undo_dir.join(name))
+}
+
+/// Writes the undo history for `path`, with the file's permissions since it
+/// holds the file's text too.
+pub fn save(undo_dir: &Path, path: &str, text: &Rope, history: &History) -> std::io::Result<()> {
+    let undo_path = undo_path(undo_dir, path)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, path.to_string()))?;
+    fs::create_dir_all(undo_dir)?;
+    let file = UndoFile {
+        hash: format!("{:016x}", content_hash(text)),
+        history: history.clone(),
+    };
+    let serialized = toml::to_string(&file).map_err(std::io::Error::other)?;
+    fileio::write_restricted(&undo_path, &serialized, path)
+}
+
+/// Loads the undo history for `path` if one exists and was written for
+/// exactly `text`.
+pub fn load(undo_dir: &Path, path: &str, text: &Rope) -> Option<History> {
+    let content = fs::read_to_string(undo_path(undo_dir, path)?).ok()?;
+    let file: UndoFile = toml::from_str(&content).ok()?;
+    if file.hash != format!("{:016x}", content_hash(text)) {
+        return None;
+    }
+    Some(file.history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::push_insert;
+
+    #[test]
+    fn history_round_trips_through_the_undo_file() {
+        let dir = std::env::temp_dir().join(format!("rune-undofile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.txt");
+        let path = file.to_str().unwrap();
+        let undo_dir = dir.join("undo");
+
+        let mut text = Rope::from_str("one\n");
+        let mut history = History::new();
+        push_insert(&mut history, &mut text, 3, " two", 10);
+        push_insert(&mut history, &mut text, 7, " three", 20);
+        history.undo(&mut text);
+        push_insert(&mut history, &mut text, 0, "zero ", 30);
+        fs::write(&file, text.to_string()).unwrap();
+        save(&undo_dir, path, &text, &history).unwrap();
+
+        assert!(load(&undo_dir, path, &Rope::from_str("other\n")).is_none());
+        let mut loaded = load(&undo_dir, path, &text).unwrap();
+        assert_eq!(loaded.current(), history.current());
+        assert_eq!(loaded.latest(), history.latest());
+        // The abandoned branch survives the trip too.
+        assert_eq!(loaded.goto(2, &mut text), Some((0, 13)));
+        assert_eq!(text.to_string(), "one two three\n");
+        loaded.goto(0, &mut text);
+        assert_eq!(text.to_string(), "one\n");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
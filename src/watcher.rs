@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+/// Canonical form of `path` used to match change events to buffers. Only the
+/// directory is resolved, so it still works once the file is gone.
+pub fn watch_key(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(std::fs::canonicalize(dir).ok()?.join(path.file_name()?))
+}
+
+/// Reports files in watched directories that change on disk. Directories are
+/// watched rather than files so replacing a file by renaming is noticed too.
+pub struct FileWatcher {
+    #[cfg(target_os = "linux")]
+    watches: inotify::Watches,
+    dirs: Arc<Mutex<HashMap<i32, PathBuf>>>,
+    events: mpsc::UnboundedReceiver<PathBuf>,
+}
+
+impl FileWatcher {
+    /// Starts watching on the tokio runtime; must be called from within it.
+    #[cfg(target_os = "linux")]
+    pub fn new() -> std::io::Result<Self> {
+        use inotify::Inotify;
+        use tokio::io::unix::AsyncFd;
+
+        let inotify = Inotify::init()?;
+        let watches = inotify.watches();
+        let dirs: Arc<Mutex<HashMap<i32, PathBuf>>> = Arc::default();
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut fd = AsyncFd::new(inotify)?;
+        let task_dirs = Arc::clone(&dirs);
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            loop {
+                let Ok(mut guard) = fd.readable_mut().await else {
+                    return;
+                };
+                let Ok(Ok(events)) = guard.try_io(|fd| fd.get_mut().read_events(&mut buffer))
+                else {
+                    continue;
+                };
+                let dirs = task_dirs.lock().unwrap();
+                for event in events {
+                    if let (Some(dir), Some(name)) =
+                        (dirs.get(&event.wd.get_watch_descriptor_id()), event.name)
+                        && sender.send(dir.join(name)).is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            watches,
+            dirs,
+            events,
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new() -> std::io::Result<Self> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Starts watching the directory holding `path`.
+    #[cfg(target_os = "linux")]
+    pub fn watch(&mut self, path: &str) {
+        use inotify::WatchMask;
+
+        let Some(dir) = watch_key(path).and_then(|key| key.parent().map(Path::to_path_buf)) else {
+            return;
+        };
+        let mask = WatchMask::MODIFY
+            | WatchMask::CLOSE_WRITE
+            | WatchMask::ATTRIB
+            | WatchMask::CREATE
+            | WatchMask::DELETE
+            | WatchMask::MOVED_FROM
+            | WatchMask::MOVED_TO;
+        if let Ok(wd) = self.watches.add(&dir, mask) {
+            self.dirs
+                .lock()
+                .unwrap()
+                .insert(wd.get_watch_descriptor_id(), dir);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn watch(&mut self, _path: &str) {}
+
+    /// Files reported changed since the last call.
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        while let Ok(path) = self.events.try_recv() {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        files
+    }
+}
@@ -0,0 +1,279 @@
+/// A viewport onto a buffer. The current window's cursor and scroll offset
+/// live on the editor while it is active and are stored here when it is not.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: usize,
+    pub buffer_id: usize,
+    pub cursor: (usize, usize),
+    pub scroll_offset: (usize, usize),
+}
+
+/// A tab page: a window layout of its own and the window focused in it.
+#[derive(Debug, Clone)]
+pub struct TabPage {
+    pub windows: Vec<Window>,
+    pub layout: Layout,
+    pub current_window: usize,
+}
+
+impl TabPage {
+    pub fn current(&self) -> Option<&Window> {
+        self.windows.iter().find(|w| w.id == self.current_window)
+    }
+
+    /// Closes every window showing buffer `id`. Returns false when that
+    /// leaves the tab page without windows.
+    pub fn close_buffer(&mut self, id: usize) -> bool {
+        let closed: Vec<usize> = self
+            .windows
+            .iter()
+            .filter(|w| w.buffer_id == id)
+            .map(|w| w.id)
+            .collect();
+        if closed.len() == self.windows.len() {
+            return false;
+        }
+        for window in closed {
+            self.layout.remove(window);
+            self.windows.retain(|w| w.id != window);
+        }
+        if self.current().is_none() {
+            self.current_window = self.layout.window_ids()[0];
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDir {
+    /// `:split`: windows stacked top to bottom.
+    Horizontal,
+    /// `:vsplit`: windows side by side.
+    Vertical,
+}
+
+/// Screen area in cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// Tree of windows. Each split shares its area among its children in
+/// proportion to `sizes`; side-by-side children are separated by a one-column
+/// border.
+#[derive(Debug, Clone)]
+pub enum Layout {
+    Window(usize),
+    Split {
+        dir: SplitDir,
+        children: Vec<Layout>,
+        sizes: Vec<f32>,
+    },
+}
+
+impl Layout {
+    /// Area of every window, in layout order, when the layout fills `area`.
+    pub fn rects(&self, area: Rect) -> Vec<(usize, Rect)> {
+        let mut rects = Vec::new();
+        self.collect_rects(area, &mut rects);
+        rects
+    }
+
+    fn collect_rects(&self, area: Rect, rects: &mut Vec<(usize, Rect)>) {
+        match self {
+            Layout::Window(id) => rects.push((*id, area)),
+            Layout::Split { children, .. } => {
+                for (child, child_area) in children.iter().zip(self.child_areas(area)) {
+                    child.collect_rects(child_area, rects);
+                }
+            }
+        }
+    }
+
+    fn child_areas(&self, area: Rect) -> Vec<Rect> {
+        let Layout::Split { dir, sizes, .. } = self else {
+            return vec![area];
+        };
+        let borders = match dir {
+            SplitDir::Horizontal => 0,
+            SplitDir::Vertical => sizes.len() as u16 - 1,
+        };
+        let total = match dir {
+            SplitDir::Horizontal => area.height,
+            SplitDir::Vertical => area.width.saturating_sub(borders),
+        };
+        let mut pos = 0;
+        distribute(total, sizes)
+            .into_iter()
+            .map(|len| {
+                let rect = match dir {
+                    SplitDir::Horizontal => Rect {
+                        y: area.y + pos,
+                        height: len,
+                        ..area
+                    },
+                    SplitDir::Vertical => Rect {
+                        x: area.x + pos,
+                        width: len,
+                        ..area
+                    },
+                };
+                pos += len + (*dir == SplitDir::Vertical) as u16;
+                rect
+            })
+            .collect()
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        match self {
+            Layout::Window(window) => *window == id,
+            Layout::Split { children, .. } => children.iter().any(|c| c.contains(id)),
+        }
+    }
+
+    /// Window ids in layout order (top to bottom, left to right).
+    pub fn window_ids(&self) -> Vec<usize> {
+        self.rects(Rect::default())
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// Splits window `target` in `dir`, putting window `new` above or to the
+    /// left of it, in half of its space.
+    pub fn split(&mut self, target: usize, new: usize, dir: SplitDir) -> bool {
+        match self {
+            Layout::Window(id) if *id == target => {
+                *self = Layout::Split {
+                    dir,
+                    children: vec![Layout::Window(new), Layout::Window(target)],
+                    sizes: vec![1.0, 1.0],
+                };
+                true
+            }
+            Layout::Window(_) => false,
+            Layout::Split {
+                dir: split_dir,
+                children,
+                sizes,
+            } => {
+                let leaf = children
+                    .iter()
+                    .position(|c| matches!(c, Layout::Window(id) if *id == target));
+                if let Some(i) = leaf
+                    && *split_dir == dir
+                {
+                    sizes[i] /= 2.0;
+                    children.insert(i, Layout::Window(new));
+                    sizes.insert(i, sizes[i]);
+                    return true;
+                }
+                children.iter_mut().any(|c| c.split(target, new, dir))
+            }
+        }
+    }
+
+    /// Removes window `id`; the next sibling (or the previous one) takes over
+    /// its space. Returns false when `id` is the only window.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let Layout::Split {
+            children, sizes, ..
+        } = self
+        else {
+            return false;
+        };
+        let leaf = children
+            .iter()
+            .position(|c| matches!(c, Layout::Window(w) if *w == id));
+        if let Some(i) = leaf {
+            children.remove(i);
+            let size = sizes.remove(i);
+            sizes[i.min(children.len() - 1)] += size;
+        } else if !children.iter_mut().any(|c| c.remove(id)) {
+            return false;
+        }
+        if children.len() == 1 {
+            let only = children.remove(0);
+            *self = only;
+        }
+        true
+    }
+
+    /// `Ctrl-w =`: gives all windows the same size.
+    pub fn equalize(&mut self) {
+        if let Layout::Split {
+            children, sizes, ..
+        } = self
+        {
+            for (child, size) in children.iter_mut().zip(sizes.iter_mut()) {
+                child.equalize();
+                *size = 1.0;
+            }
+        }
+    }
+
+    /// Grows window `id` by `delta` cells in `dir` (height for `Horizontal`,
+    /// width for `Vertical`), taking the space from a neighbour. `area` is the
+    /// area the whole layout fills.
+    pub fn resize(&mut self, id: usize, dir: SplitDir, delta: i32, area: Rect) -> bool {
+        let child_areas = self.child_areas(area);
+        let Layout::Split {
+            dir: split_dir,
+            children,
+            sizes,
+        } = self
+        else {
+            return false;
+        };
+        let Some(i) = children.iter().position(|c| c.contains(id)) else {
+            return false;
+        };
+        if children[i].resize(id, dir, delta, child_areas[i]) {
+            return true;
+        }
+        if *split_dir != dir || children.len() < 2 {
+            return false;
+        }
+
+        // Work in cells so the other children keep their current size.
+        let lens: Vec<i32> = child_areas
+            .iter()
+            .map(|r| match dir {
+                SplitDir::Horizontal => r.height as i32,
+                SplitDir::Vertical => r.width as i32,
+            })
+            .collect();
+        let neighbour = if i + 1 < children.len() { i + 1 } else { i - 1 };
+        let delta = delta.max(1 - lens[i]).min(lens[neighbour] - 1);
+        for (size, len) in sizes.iter_mut().zip(&lens) {
+            *size = *len as f32;
+        }
+        sizes[i] += delta as f32;
+        sizes[neighbour] -= delta as f32;
+        true
+    }
+}
+
+/// Splits `total` cells in proportion to `weights`, handing out the cells
+/// lost to rounding from the front.
+fn distribute(total: u16, weights: &[f32]) -> Vec<u16> {
+    // Keeps a zero weight from dividing by zero.
+    let weight = |w: f32| w.max(0.001);
+    let sum: f32 = weights.iter().map(|&w| weight(w)).sum();
+    let mut lens: Vec<u16> = weights
+        .iter()
+        .map(|&w| (total as f32 * weight(w) / sum).floor() as u16)
+        .collect();
+    let mut rest = total.saturating_sub(lens.iter().sum());
+    for len in lens.iter_mut() {
+        if rest == 0 {
+            break;
+        }
+        *len += 1;
+        rest -= 1;
+    }
+    lens
+}